@@ -1,37 +1,244 @@
 use rodio::{
     Sink,
-    OutputStream, 
+    Source,
+    OutputStream,
     OutputStreamBuilder
 };
 
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::synth::{Synth, Tone, FPS, SAMPLE_RATE};
+
+// Frames kept queued ahead of the output, more are played faster
+const TARGET_LATENCY: usize = 3;
+
+// Frames queued before playback starts, and again after an underrun
+const PREBUFFER: usize = 2;
+
 pub struct Audio {
-    sink: Sink,
-    _stream: OutputStream
+    // None when muted
+    output: Option<(Sink, OutputStream)>,
+    timeline: Arc<Mutex<VecDeque<bool>>>, // Beeper state of the emulated frames not played yet
+    wav: Option<WavExport>
 }
 
 impl Audio {
     // New audio
-    pub fn new() -> Result<Audio, String> {
-        
+    pub fn new(tone: Tone) -> Result<Audio, String> {
+
         let stream_handle: OutputStream = match OutputStreamBuilder::open_default_stream() {
             Ok(v) => v,
             Err(err) => {return Err(err.to_string());}
         };
 
-        let sink: Sink = Sink::connect_new(&stream_handle.mixer());
+        let sink: Sink = Sink::connect_new(stream_handle.mixer());
+
+        // The sink keeps playing, the beeper follows the emulated frames
+        let timeline = Arc::new(Mutex::new(VecDeque::new()));
+        sink.append(Beeper::new(tone, timeline.clone()));
+
+        Ok(Audio{output: Some((sink, stream_handle)), timeline, wav: None})
+    }
+
+    // Audio that never opens an output device, for --mute and machines
+    // without one
+    pub fn muted() -> Audio {
+        Audio{output: None, timeline: Arc::new(Mutex::new(VecDeque::new())), wav: None}
+    }
+
+    // Also render every emulated frame into a WAV file
+    pub fn start_wav(&mut self, path: &Path, tone: Tone) -> Result<(), String> {
+        self.wav = Some(WavExport::create(path, tone)?);
+        Ok(())
+    }
+
+    // Queue one emulated frame, with the beeper on or off during it
+    pub fn frame(&mut self, beeping: bool) {
+        if self.output.is_some() {
+            self.timeline.lock().unwrap().push_back(beeping);
+        }
+
+        if let Some(wav) = &mut self.wav
+            && let Err(err) = wav.frame(beeping) {
+            eprintln!("WAV export stopped: {}", err);
+            self.wav = None;
+        }
+    }
+}
+
+// 16 bit mono WAV rendered from the emulated frames, independent of the
+// sound card and of how fast the emulator runs
+pub struct WavExport {
+    path: PathBuf,
+    writer: Option<hound::WavWriter<BufWriter<File>>>,
+    synth: Synth,
+    frames: u64
+}
+
+impl WavExport {
+    pub fn create(path: &Path, tone: Tone) -> Result<WavExport, String> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int
+        };
+        let writer = hound::WavWriter::create(path, spec).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Ok(WavExport { path: path.to_path_buf(), writer: Some(writer), synth: Synth::new(tone), frames: 0 })
+    }
+
+    pub fn frame(&mut self, beeping: bool) -> Result<(), String> {
+        if let Some(writer) = &mut self.writer {
+            for _ in 0..Synth::frame_samples(self.frames) {
+                let level = self.synth.sample(beeping);
+                writer.write_sample((level * i16::MAX as f32) as i16).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+            }
+        }
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    // Write the header sizes and close the file
+    pub fn finish(&mut self) -> Result<(), String> {
+        match self.writer.take() {
+            Some(writer) => writer.finalize().map_err(|e| format!("{}: {}", self.path.display(), e)),
+            None => Ok(())
+        }
+    }
+}
+
+impl Drop for WavExport {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            eprintln!("Could not finish WAV file: {}", err);
+        }
+    }
+}
+
+// Endless tone synthesised from the emulated frames, so a beep lasts
+// exactly as many frames as the sound timer ran
+// Frames are played at the emulated rate, faster when they pile up
+// (fast-forward), with silence when they run out (pause, slow motion)
+struct Beeper {
+    synth: Synth,
+    timeline: Arc<Mutex<VecDeque<bool>>>,
+    frames: VecDeque<bool>, // Taken from the timeline, not played yet
+    playing: bool, // False until PREBUFFER frames are queued
+    on: bool, // State of the frame being played
+    position: f32, // Progress through that frame, 0..1
+    step: f32 // Progress per sample
+}
+
+impl Beeper {
+    fn new(tone: Tone, timeline: Arc<Mutex<VecDeque<bool>>>) -> Beeper {
+        Beeper {
+            synth: Synth::new(tone),
+            timeline,
+            frames: VecDeque::new(),
+            playing: false,
+            on: false,
+            position: 1.0,
+            step: FPS as f32 / SAMPLE_RATE as f32
+        }
+    }
+
+    // Move on to the next emulated frame when the current one is done
+    fn advance(&mut self) {
+        if self.position < 1.0 {
+            return;
+        }
+        self.position -= 1.0;
+
+        // One lock per played frame, taking every frame queued since
+        self.frames.extend(self.timeline.lock().unwrap().drain(..));
+        if self.frames.len() >= PREBUFFER {
+            self.playing = true;
+        }
+
+        let next = if self.playing { self.frames.pop_front() } else { None };
+        match next {
+            Some(on) => {
+                self.on = on;
+                let speed = (self.frames.len() as f32 / TARGET_LATENCY as f32).max(1.0);
+                self.step = speed * FPS as f32 / SAMPLE_RATE as f32;
+            }
+            None => {
+                // Underrun, play a silent frame and wait for the buffer to
+                // fill again, no frame is skipped or played longer
+                self.playing = false;
+                self.on = false;
+                self.step = FPS as f32 / SAMPLE_RATE as f32;
+            }
+        }
+    }
+}
+
+impl Iterator for Beeper {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.advance();
+        self.position += self.step;
 
-        // Sinewave
-        sink.append(rodio::source::SineWave::new(440.0));
-        sink.pause();
+        Some(self.synth.sample(self.on))
+    }
+}
+
+impl Source for Beeper {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
 
-        Ok(Audio{sink, _stream: stream_handle})
+    fn total_duration(&self) -> Option<Duration> {
+        None
     }
+}
 
-    //Play or pause audio
-    pub fn play(&self) {
-        self.sink.play();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // State in the middle of each of the next played frames
+    fn play(beeper: &mut Beeper, frames: usize) -> Vec<bool> {
+        let samples = (SAMPLE_RATE / FPS) as usize;
+        (0..frames).map(|_| {
+            beeper.by_ref().take(samples / 2).for_each(drop);
+            let on = beeper.on;
+            beeper.by_ref().take(samples - samples / 2).for_each(drop);
+            on
+        }).collect()
     }
-    pub fn pause(&self) {
-        self.sink.pause();
+
+    #[test]
+    fn prebuffer_and_underrun() {
+        let timeline = Arc::new(Mutex::new(VecDeque::new()));
+        let mut beeper = Beeper::new(Tone::default(), timeline.clone());
+
+        // Waits for PREBUFFER frames before playing
+        timeline.lock().unwrap().push_back(true);
+        assert_eq!(play(&mut beeper, 2), [false, false]);
+        timeline.lock().unwrap().push_back(true);
+        assert_eq!(play(&mut beeper, 2), [true, true]);
+
+        // Underrun is silent and the next frame is not lost
+        assert_eq!(play(&mut beeper, 1), [false]);
+        timeline.lock().unwrap().extend([true, false]);
+        assert_eq!(play(&mut beeper, 3), [true, false, false]);
+        assert!(beeper.frames.is_empty() && timeline.lock().unwrap().is_empty());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,212 @@
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use crate::cli::{Args, BatchArgs};
+use crate::config::{Config, Settings};
+use crate::cpu::CPU;
+use crate::database::Database;
+use crate::movie::Movie;
+
+// One entry of the jobs file, paths are relative to the jobs file
+//
+// [
+//     { "rom": "pong.ch8", "movie": "pong.txt" },
+//     { "rom": "brix.ch8", "frames": 3600, "quirks": "vip", "seed": 7 }
+// ]
+//
+// Any config file setting can be given, on top of the config file and the
+// ROM database as in a normal run
+#[derive(Deserialize, Debug)]
+struct Job {
+    rom: PathBuf,
+    movie: Option<PathBuf>,
+    frames: Option<u64>, // Defaults to the movie length, then --frames
+    #[serde(flatten)]
+    settings: Settings
+}
+
+#[derive(Serialize, Debug)]
+struct JobResult {
+    rom: PathBuf,
+    movie: Option<PathBuf>,
+    rom_sha1: Option<String>,
+    frames: u64,
+    cycles: u64,
+    screen_sha1: Option<String>, // Display pixels when the job ended
+    crash: Option<String>, // Machine fault, the job stopped early
+    error: Option<String> // The job could not start
+}
+
+#[derive(Serialize, Debug)]
+struct Summary {
+    jobs: usize,
+    completed: usize,
+    crashed: usize,
+    failed: usize,
+    seconds: f64,
+    results: Vec<JobResult>
+}
+
+// Run every job on headless machines spread over threads, then write the
+// JSON summary to --output or stdout
+// Returns how many jobs could not start, crashes are results like any other
+pub fn run(args: BatchArgs) -> Result<usize, String> {
+    let config = match &args.config {
+        Some(path) => Config::load_from(path)?,
+        None => Config::load()?
+    };
+
+    let text = std::fs::read_to_string(&args.jobs).map_err(|err| format!("{}: {}", args.jobs.display(), err))?;
+    let jobs: Vec<Job> = serde_json::from_str(&text).map_err(|err| format!("{}: {}", args.jobs.display(), err))?;
+    let base = args.jobs.parent().unwrap_or(Path::new("."));
+
+    let database = match Database::load() {
+        Ok(db) => Some(db),
+        Err(err) => {
+            eprintln!("Could not load ROM database: {}", err);
+            None
+        }
+    };
+
+    let threads = args.threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+        .clamp(1, jobs.len().max(1));
+
+    let start = Instant::now();
+    let next = AtomicUsize::new(0);
+
+    // Threads take the next job until none are left
+    let mut results: Vec<(usize, JobResult)> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads).map(|_| scope.spawn(|| {
+            let mut done = Vec::new();
+            loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(job) = jobs.get(index) else { break; };
+
+                let result = run_job(job, base, &config, database.as_ref(), args.frames);
+                match (&result.error, &result.crash) {
+                    (Some(err), _) => eprintln!("[{}/{}] {}: {}", index + 1, jobs.len(), job.rom.display(), err),
+                    (None, Some(crash)) => eprintln!("[{}/{}] {}: {}", index + 1, jobs.len(), job.rom.display(), crash),
+                    (None, None) => eprintln!("[{}/{}] {}: {} frames", index + 1, jobs.len(), job.rom.display(), result.frames)
+                }
+                done.push((index, result));
+            }
+            done
+        })).collect();
+
+        workers.into_iter().flat_map(|w| w.join().unwrap()).collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+
+    let results: Vec<JobResult> = results.into_iter().map(|(_, r)| r).collect();
+    let failed = results.iter().filter(|r| r.error.is_some()).count();
+    let crashed = results.iter().filter(|r| r.crash.is_some()).count();
+
+    let summary = Summary {
+        jobs: results.len(),
+        completed: results.len() - failed - crashed,
+        crashed,
+        failed,
+        seconds: start.elapsed().as_secs_f64(),
+        results
+    };
+
+    let json = serde_json::to_string_pretty(&summary).map_err(|err| err.to_string())?;
+    match &args.output {
+        Some(path) => std::fs::write(path, json + "\n").map_err(|err| format!("{}: {}", path.display(), err))?,
+        None => println!("{}", json)
+    }
+
+    Ok(failed)
+}
+
+fn run_job(job: &Job, base: &Path, config: &Config, database: Option<&Database>, default_frames: Option<u64>) -> JobResult {
+    let mut result = JobResult {
+        rom: job.rom.clone(),
+        movie: job.movie.clone(),
+        rom_sha1: None,
+        frames: 0,
+        cycles: 0,
+        screen_sha1: None,
+        crash: None,
+        error: None
+    };
+
+    match start_job(job, base, config, database, default_frames) {
+        Ok((mut cpu, movie, frames, rom_sha1)) => {
+            result.rom_sha1 = Some(rom_sha1);
+
+            while cpu.frames() < frames {
+                let keypad = movie.keypad(cpu.frames());
+                if let Err(err) = cpu.run_frame(keypad) {
+                    result.crash = Some(format!("{} at {:#05X}", err, cpu.pc()));
+                    break;
+                }
+            }
+
+            result.frames = cpu.frames();
+            result.cycles = cpu.cycles();
+            result.screen_sha1 = Some(sha1_hex(cpu.display().pixels()));
+        }
+        Err(err) => result.error = Some(err)
+    }
+
+    result
+}
+
+// Machine ready to run, the movie, the frame count and the ROM hash
+fn start_job(job: &Job, base: &Path, config: &Config, database: Option<&Database>,
+             default_frames: Option<u64>) -> Result<(CPU, Movie, u64, String), String> {
+
+    job.settings.check()?;
+
+    let rom_path = base.join(&job.rom);
+    let rom = std::fs::read(&rom_path).map_err(|err| format!("Could not load ROM: {}", err))?;
+
+    let movie = match &job.movie {
+        Some(path) => Movie::load(&base.join(path))?,
+        None => Movie::default()
+    };
+
+    let frames = match job.frames.or((!movie.is_empty()).then_some(movie.len())).or(default_frames) {
+        Some(f) => f,
+        None => {return Err(String::from("No frame count, give frames, a movie or --frames"));}
+    };
+
+    let rom_info = database.and_then(|db| db.lookup(&rom));
+    let args = Args {
+        rom: rom_path,
+        config: None,
+        settings: job.settings.clone(),
+        headless: true,
+        debug: false,
+        frames: Some(frames),
+        record: None,
+        wav: None,
+        terminal: false,
+        trace: None,
+        trace_range: None,
+        compare: None,
+        movie: None,
+        profile: None
+    };
+    let options = config.options(args, rom_info.as_ref().map(|i| &i.settings))
+        .map_err(|err| format!("Invalid settings: {}", err))?;
+
+    let mut cpu = CPU::new();
+    cpu.set_quirks(options.quirks);
+    cpu.set_speed(options.speed);
+    // Fixed seed so reruns give the same results
+    cpu.seed_rng(options.seed.unwrap_or(0));
+    cpu.load_program(&rom).map_err(|err| format!("Could not load ROM: {}", err))?;
+
+    Ok((cpu, movie, frames, sha1_hex(&rom)))
+}
+
+fn sha1_hex(data: &[u8]) -> String {
+    Sha1::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
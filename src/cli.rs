@@ -0,0 +1,196 @@
+use std::path::PathBuf;
+
+use crate::config::Settings;
+
+// Command line arguments
+// Settings given here override the config file
+#[derive(Debug)]
+pub struct Args {
+    pub rom: PathBuf,
+    pub config: Option<PathBuf>,
+    pub settings: Settings,
+    pub headless: bool,
+    pub debug: bool,
+    pub frames: Option<u64>,
+    pub record: Option<PathBuf>,
+    pub wav: Option<PathBuf>,
+    pub terminal: bool,
+    pub trace: Option<PathBuf>,
+    pub trace_range: Option<String>,
+    pub compare: Option<PathBuf>,
+    pub movie: Option<PathBuf>,
+    pub profile: Option<PathBuf>
+}
+
+// chip8 batch <jobs.json>
+#[derive(Debug)]
+pub struct BatchArgs {
+    pub jobs: PathBuf,
+    pub config: Option<PathBuf>,
+    pub threads: Option<usize>,
+    pub frames: Option<u64>, // For jobs without frames or a movie
+    pub output: Option<PathBuf>
+}
+
+pub fn usage(program: &str) -> String {
+    format!("Usage: {0} [options] <rom-file-name>
+       {0} batch [options] <jobs.json>
+
+Options:
+    --scale <n>         Initial window scale (default 16)
+    --scaling <mode>    Fit to the window: integer, aspect, stretch
+    --theme <name>      Colour theme: mono, green, amber, lcd, octo (F2 cycles)
+    --fg <RRGGBB>       Foreground colour
+    --bg <RRGGBB>       Background colour
+    --filter <mode>     Anti-flicker filter: none, persistence, or (2-frame OR)
+    --speed <n>         Instructions per frame (default 12)
+    --quirks <preset>   Quirk preset: default, vip, schip, xochip
+    --clip              Clip sprites at the screen edges
+    --wrap              Wrap sprites around the screen edges
+    --seed <n>          Seed for the random number generator
+    --headless          Run without a window
+    --terminal          Draw in the terminal instead of a window
+    --terminal-mode <m> Terminal drawing: halfblock, braille
+    --frames <n>        Stop after n frames
+    --mute              Disable audio without opening a device
+    --tone <hz>         Beeper frequency (default 440)
+    --waveform <w>      Beeper waveform: square, sine, triangle, sawtooth
+    --volume <v>        Beeper volume from 0 to 1 (default 0.25)
+    --debug             Print the machine state when stopping, read commands
+                        (screenshot) from stdin
+    --fullscreen        Borderless window filling the screen (F11 toggles)
+    --screenshot-dir <dir>  Where F12 screenshots and F10 recordings go (default .)
+    --record <file>     Record from the start to a .gif, or .y4m plus .wav
+    --record-format <f> Format of F10 recordings: gif, y4m
+    --wav <file>        Render the beeper to a WAV file from the emulated frames
+    --trace <file>      Log every executed instruction to a file
+    --trace-range <a-b> Only log instructions at these hex addresses, like 200-2FF
+    --compare <file>    Run headless against another emulator's trace and report
+                        the first difference
+    --movie <file>      Keys to hold on each frame during --compare
+    --profile <file>    Write execution counts, memory use and an annotated
+                        disassembly to a file when stopping
+    --config <file>     Config file (default ~/.config/chip8/config.toml)
+    -h, --help          Show this message
+
+Batch options:
+    --threads <n>       Machines running at once (default one per CPU)
+    --frames <n>        Frames for jobs without frames or a movie
+    --output <file>     Write the JSON summary here instead of stdout
+    --config <file>     Config file (default ~/.config/chip8/config.toml)", program)
+}
+
+// Parse arguments, without the program name
+// Ok(None) means help was requested
+pub fn parse(args: &[String]) -> Result<Option<Args>, String> {
+    let mut rom: Option<PathBuf> = None;
+    let mut config: Option<PathBuf> = None;
+    let mut settings = Settings::default();
+    let mut headless = false;
+    let mut debug = false;
+    let mut frames: Option<u64> = None;
+    let mut record: Option<PathBuf> = None;
+    let mut wav: Option<PathBuf> = None;
+    let mut terminal = false;
+    let mut trace: Option<PathBuf> = None;
+    let mut trace_range: Option<String> = None;
+    let mut compare: Option<PathBuf> = None;
+    let mut movie: Option<PathBuf> = None;
+    let mut profile: Option<PathBuf> = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {return Ok(None);}
+            "--scale" => settings.scale = Some(number(arg, iter.next())?),
+            "--theme" => settings.theme = Some(value(arg, iter.next())?),
+            "--scaling" => settings.scaling = Some(value(arg, iter.next())?),
+            "--fg" => settings.foreground = Some(value(arg, iter.next())?),
+            "--bg" => settings.background = Some(value(arg, iter.next())?),
+            "--filter" => settings.filter = Some(value(arg, iter.next())?),
+            "--speed" => settings.speed = Some(number(arg, iter.next())?),
+            "--quirks" => settings.quirks = Some(value(arg, iter.next())?),
+            "--clip" => settings.clip = Some(true),
+            "--wrap" => settings.clip = Some(false),
+            "--seed" => settings.seed = Some(number(arg, iter.next())?),
+            "--headless" => headless = true,
+            "--terminal" => terminal = true,
+            "--terminal-mode" => settings.terminal_mode = Some(value(arg, iter.next())?),
+            "--frames" => frames = Some(number(arg, iter.next())?),
+            "--mute" => settings.mute = Some(true),
+            "--tone" => settings.tone = Some(number(arg, iter.next())?),
+            "--waveform" => settings.waveform = Some(value(arg, iter.next())?),
+            "--volume" => settings.volume = Some(number(arg, iter.next())?),
+            "--debug" => debug = true,
+            "--fullscreen" => settings.fullscreen = Some(true),
+            "--record" => record = Some(PathBuf::from(value(arg, iter.next())?)),
+            "--wav" => wav = Some(PathBuf::from(value(arg, iter.next())?)),
+            "--trace" => trace = Some(PathBuf::from(value(arg, iter.next())?)),
+            "--trace-range" => trace_range = Some(value(arg, iter.next())?),
+            "--compare" => compare = Some(PathBuf::from(value(arg, iter.next())?)),
+            "--movie" => movie = Some(PathBuf::from(value(arg, iter.next())?)),
+            "--profile" => profile = Some(PathBuf::from(value(arg, iter.next())?)),
+            "--record-format" => settings.record_format = Some(value(arg, iter.next())?),
+            "--screenshot-dir" => settings.screenshot_dir = Some(PathBuf::from(value(arg, iter.next())?)),
+            "--config" => config = Some(PathBuf::from(value(arg, iter.next())?)),
+            flag if flag.starts_with('-') => {return Err(format!("Unknown option '{}'", flag));}
+            path => {
+                if rom.is_some() {
+                    return Err(format!("Unexpected argument '{}'", path));
+                }
+                rom = Some(PathBuf::from(path));
+            }
+        }
+    }
+
+    match rom {
+        Some(rom) => Ok(Some(Args { rom, config, settings, headless, debug, frames, record, wav, terminal, trace, trace_range, compare, movie, profile })),
+        None => Err(String::from("Missing ROM file"))
+    }
+}
+
+// Arguments after "batch"
+// Ok(None) means help was requested
+pub fn parse_batch(args: &[String]) -> Result<Option<BatchArgs>, String> {
+    let mut jobs: Option<PathBuf> = None;
+    let mut config: Option<PathBuf> = None;
+    let mut threads: Option<usize> = None;
+    let mut frames: Option<u64> = None;
+    let mut output: Option<PathBuf> = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {return Ok(None);}
+            "--threads" => threads = Some(number(arg, iter.next())?),
+            "--frames" => frames = Some(number(arg, iter.next())?),
+            "--output" => output = Some(PathBuf::from(value(arg, iter.next())?)),
+            "--config" => config = Some(PathBuf::from(value(arg, iter.next())?)),
+            flag if flag.starts_with('-') => {return Err(format!("Unknown option '{}'", flag));}
+            path => {
+                if jobs.is_some() {
+                    return Err(format!("Unexpected argument '{}'", path));
+                }
+                jobs = Some(PathBuf::from(path));
+            }
+        }
+    }
+
+    if threads == Some(0) {
+        return Err(String::from("--threads must be at least 1"));
+    }
+
+    match jobs {
+        Some(jobs) => Ok(Some(BatchArgs { jobs, config, threads, frames, output })),
+        None => Err(String::from("Missing jobs file"))
+    }
+}
+
+fn value(flag: &str, next: Option<&String>) -> Result<String, String> {
+    next.cloned().ok_or(format!("Missing value for {}", flag))
+}
+
+fn number<T: std::str::FromStr>(flag: &str, next: Option<&String>) -> Result<T, String> {
+    let text = value(flag, next)?;
+    text.parse().map_err(|_| format!("Invalid value '{}' for {}", text, flag))
+}
@@ -0,0 +1,209 @@
+use std::path::Path;
+
+use crate::cpu::CPU;
+use crate::disasm::disassemble;
+use crate::movie::Movie;
+use crate::trace::Registers;
+
+// Reference trace from another emulator, one line per instruction with the
+// state before it runs, as KEY=hex fields in any order
+//
+//     # comment
+//     PC=200 OP=601E I=000 SP=0 DT=00 ST=00 V0=00 V1=00 ... VF=00
+//     PC=202 OP=F018 I=000 SP=0 DT=00 ST=00 V=1E000000000000000000000000000000
+//
+// Keys: PC, OP, I, SP, DT, ST, V0 to VF, or V with all 16 registers as 32
+// hex digits. Fields left out are not compared, unknown keys are ignored
+#[derive(Clone, Default, Debug)]
+struct Expected {
+    line: usize,
+    pc: Option<usize>,
+    opcode: Option<u16>,
+    i: Option<usize>,
+    sp: Option<usize>,
+    dt: Option<u8>,
+    st: Option<u8>,
+    v: [Option<u8>; 16]
+}
+
+// How many traced instructions before the divergence are shown
+const CONTEXT: usize = 8;
+
+fn parse(text: &str) -> Result<Vec<Expected>, String> {
+    let mut states = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut state = Expected { line: number + 1, ..Expected::default() };
+        for field in line.split_whitespace() {
+            let Some((key, value)) = field.split_once('=') else {
+                return Err(format!("line {}: expected KEY=value, got '{}'", number + 1, field));
+            };
+            let key = key.to_ascii_uppercase();
+            let value = value.trim_start_matches("0x").trim_start_matches("0X");
+            let hex = |max: u64| match u64::from_str_radix(value, 16) {
+                Ok(n) if n <= max => Ok(n),
+                _ => Err(format!("line {}: invalid value '{}' for {}", number + 1, value, key))
+            };
+
+            match key.as_str() {
+                "PC" => state.pc = Some(hex(0xFFFF)? as usize),
+                "OP" => state.opcode = Some(hex(0xFFFF)? as u16),
+                "I" => state.i = Some(hex(0xFFFF)? as usize),
+                "SP" => state.sp = Some(hex(0xFF)? as usize),
+                "DT" => state.dt = Some(hex(0xFF)? as u8),
+                "ST" => state.st = Some(hex(0xFF)? as u8),
+                "V" => {
+                    if value.len() != 32 || !value.is_ascii() {
+                        return Err(format!("line {}: V needs 32 hex digits", number + 1));
+                    }
+                    for (x, register) in state.v.iter_mut().enumerate() {
+                        match u8::from_str_radix(&value[x * 2..x * 2 + 2], 16) {
+                            Ok(n) => *register = Some(n),
+                            Err(_) => {return Err(format!("line {}: invalid value '{}' for V", number + 1, value));}
+                        }
+                    }
+                }
+                register if register.len() == 2 && register.starts_with('V') => {
+                    match usize::from_str_radix(&register[1..], 16) {
+                        Ok(x) => state.v[x] = Some(hex(0xFF)? as u8),
+                        Err(_) => continue
+                    }
+                }
+                _ => continue
+            }
+        }
+        states.push(state);
+    }
+
+    Ok(states)
+}
+
+// Our state before the next instruction
+struct Actual {
+    pc: usize,
+    opcode: Option<u16>,
+    registers: Registers
+}
+
+impl Actual {
+    fn of(cpu: &CPU) -> Actual {
+        let ram = cpu.ram();
+        let opcode = match (ram.get(cpu.pc()), ram.get(cpu.pc() + 1)) {
+            (Some(&high), Some(&low)) => Some((high as u16) << 8 | low as u16),
+            _ => None
+        };
+        Actual { pc: cpu.pc(), opcode, registers: cpu.registers() }
+    }
+
+    // Name, ours, theirs for every field the reference gives
+    fn fields(&self, expected: &Expected) -> Vec<(String, String, Option<String>)> {
+        let r = &self.registers;
+        let mut fields = vec![
+            (String::from("PC"), format!("{:03X}", self.pc), expected.pc.map(|v| format!("{:03X}", v))),
+            (String::from("OP"), self.opcode.map_or(String::from("----"), |o| format!("{:04X}", o)),
+                expected.opcode.map(|v| format!("{:04X}", v))),
+            (String::from("I"), format!("{:03X}", r.i), expected.i.map(|v| format!("{:03X}", v))),
+            (String::from("SP"), format!("{:X}", r.sp), expected.sp.map(|v| format!("{:X}", v))),
+            (String::from("DT"), format!("{:02X}", r.dt), expected.dt.map(|v| format!("{:02X}", v))),
+            (String::from("ST"), format!("{:02X}", r.st), expected.st.map(|v| format!("{:02X}", v)))
+        ];
+        for x in 0..16 {
+            fields.push((format!("V{:X}", x), format!("{:02X}", r.v[x]), expected.v[x].map(|v| format!("{:02X}", v))));
+        }
+        fields
+    }
+
+    fn matches(&self, expected: &Expected) -> bool {
+        self.fields(expected).iter().all(|(_, ours, theirs)| theirs.as_ref().is_none_or(|t| t == ours))
+    }
+}
+
+// Run the ROM against the reference trace and report the first difference
+// The CPU should have its quirks, speed and a tracer set and the ROM loaded
+// Returns whether the whole trace matched, with the report
+// Err only when the comparison could not run
+pub fn run(cpu: &mut CPU, reference: &Path, movie: Option<&Path>) -> Result<(bool, String), String> {
+    let text = std::fs::read_to_string(reference).map_err(|err| format!("{}: {}", reference.display(), err))?;
+    let expected = parse(&text).map_err(|err| format!("{}: {}", reference.display(), err))?;
+    let movie = match movie {
+        Some(path) => Movie::load(path)?,
+        None => Movie::default()
+    };
+
+    for (index, state) in expected.iter().enumerate() {
+        let actual = Actual::of(cpu);
+        if !actual.matches(state) {
+            return Ok((false, report(cpu, index, state, &actual)));
+        }
+
+        let opcode = actual.opcode.unwrap_or(0);
+        if let Err(err) = cpu.step(movie.keypad(cpu.frames())) {
+            return Ok((false, format!("Our machine faulted on instruction {} (reference line {}): {} at {:#05X}: {:04X}  {}",
+                index, state.line, err, actual.pc, opcode, disassemble(opcode))));
+        }
+
+        // Random numbers cannot match, take Cxkk results from the reference
+        if opcode & 0xF000 == 0xC000 {
+            let x = ((opcode & 0x0F00) >> 8) as usize;
+            if let Some(value) = expected.get(index + 1).and_then(|next| next.v[x]) {
+                cpu.set_register(x, value);
+            }
+        }
+    }
+
+    Ok((true, format!("No divergence in {} instructions ({} frames)", expected.len(), cpu.frames())))
+}
+
+fn report(cpu: &CPU, index: usize, expected: &Expected, actual: &Actual) -> String {
+    let mut report = format!("Divergence before instruction {} (reference line {}), frame {}\n", index, expected.line, cpu.frames());
+
+    if let Some(tracer) = cpu.tracer() {
+        let history: Vec<_> = tracer.history().collect();
+        report += "\nLast instructions:\n";
+        for entry in &history[history.len().saturating_sub(CONTEXT)..] {
+            report += &format!("{}\n", entry);
+        }
+    }
+
+    report += &format!("\n{:<4} {:>6} {:>10}\n", "", "ours", "reference");
+    for (name, ours, theirs) in actual.fields(expected) {
+        let differs = theirs.as_ref().is_some_and(|t| *t != ours);
+        report += &format!("{:<4} {:>6} {:>10}{}\n", name, ours, theirs.unwrap_or(String::from("-")), if differs { "  <" } else { "" });
+    }
+
+    report.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fields() {
+        let states = parse("# header\n\nPC=200 OP=601E I=0x2A0 SP=1 DT=3C ST=00 V0=1E vf=01 X=9 # note\nPC=202 V=000102030405060708090A0B0C0D0E0F\n").unwrap();
+        assert_eq!(states.len(), 2);
+
+        let first = &states[0];
+        assert_eq!(first.line, 3);
+        assert_eq!((first.pc, first.opcode, first.i, first.sp), (Some(0x200), Some(0x601E), Some(0x2A0), Some(1)));
+        assert_eq!((first.dt, first.st), (Some(0x3C), Some(0)));
+        assert_eq!((first.v[0], first.v[1], first.v[0xF]), (Some(0x1E), None, Some(1)));
+
+        let second = &states[1];
+        assert_eq!((second.line, second.opcode, second.i), (4, None, None));
+        assert_eq!(second.v.map(|v| v.unwrap()), core::array::from_fn(|x| x as u8));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for text in ["PC", "PC=20G", "DT=100", "V=00", "V=0001020304050607080910111213141G", "V3=1FF"] {
+            assert!(parse(text).is_err(), "{}", text);
+        }
+        assert!(parse("PC=200\nOP").unwrap_err().starts_with("line 2:"));
+    }
+}
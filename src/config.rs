@@ -0,0 +1,420 @@
+use serde::Deserialize;
+use serde::de::IgnoredAny;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::synth::{Tone, Waveform};
+use crate::cli::Args;
+use crate::filter::FilterMode;
+use crate::keymap::Keymap;
+use crate::palette::Palette;
+use crate::quirks::Quirks;
+use crate::terminal::TerminalMode;
+use crate::trace;
+use crate::window::Scaling;
+use crate::display::SpriteMode;
+
+const DEFAULT_SCALE: usize = 16;
+const DEFAULT_SPEED: usize = 12; // Instructions per frame
+const DEFAULT_PERSISTENCE: f32 = 0.6;
+
+// Settings shared by the config file sections and the command line
+// Unset values fall through to the next level
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct Settings {
+    pub scale: Option<usize>, // Initial window size
+    pub scaling: Option<String>, // integer, aspect or stretch
+    pub theme: Option<String>, // Built-in palette name
+    pub palette: Option<Vec<String>>, // 2, 4 or 16 "#RRGGBB" colours, overrides the theme
+    pub foreground: Option<String>, // Override palette colours 1 and 0
+    pub background: Option<String>,
+    pub filter: Option<String>, // none, persistence or "or" (last two frames combined)
+    pub persistence: Option<f32>, // Brightness kept per frame by the persistence filter
+    pub speed: Option<usize>,
+    pub quirks: Option<String>, // Preset name
+    // Individual quirks, override the preset
+    pub vf_reset: Option<bool>,
+    pub shift_vy: Option<bool>,
+    pub memory_increment: Option<bool>,
+    pub jump_vx: Option<bool>,
+    pub clip: Option<bool>,
+    pub seed: Option<u64>,
+    pub mute: Option<bool>,
+    pub tone: Option<f32>, // Beeper frequency in Hz
+    pub waveform: Option<String>, // square, sine, triangle or sawtooth
+    pub volume: Option<f32>, // 0 to 1
+    pub attack: Option<f32>, // Beeper fade in, milliseconds
+    pub release: Option<f32>, // Beeper fade out, milliseconds
+    pub fullscreen: Option<bool>,
+    pub screenshot_dir: Option<PathBuf>, // Also used for recordings
+    pub record_format: Option<String>, // gif or y4m
+    pub terminal_mode: Option<String>, // halfblock or braille
+    pub keymap: BTreeMap<String, Vec<String>>,
+    // Keys that match no setting, rejected by check so typos are not
+    // silently ignored (deny_unknown_fields does not work with flatten)
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, IgnoredAny>
+}
+
+impl Settings {
+    // Err naming the first unknown key
+    pub fn check(&self) -> Result<(), String> {
+        match self.unknown.keys().next() {
+            Some(key) => Err(format!("Unknown setting '{}'", key)),
+            None => Ok(())
+        }
+    }
+
+    // Values set in other take precedence
+    // A preset in other also drops the individual quirks set here
+    pub fn merge(&mut self, other: &Settings) {
+        if other.quirks.is_some() {
+            self.vf_reset = None;
+            self.shift_vy = None;
+            self.memory_increment = None;
+            self.jump_vx = None;
+            self.clip = None;
+        }
+
+        if other.scale.is_some() { self.scale = other.scale; }
+        if other.scaling.is_some() { self.scaling = other.scaling.clone(); }
+        if other.theme.is_some() || other.palette.is_some() {
+            self.theme = None;
+            self.palette = None;
+            self.foreground = None;
+            self.background = None;
+        }
+        if other.theme.is_some() { self.theme = other.theme.clone(); }
+        if other.palette.is_some() { self.palette = other.palette.clone(); }
+        if other.foreground.is_some() { self.foreground = other.foreground.clone(); }
+        if other.background.is_some() { self.background = other.background.clone(); }
+        if other.filter.is_some() { self.filter = other.filter.clone(); }
+        if other.persistence.is_some() { self.persistence = other.persistence; }
+        if other.speed.is_some() { self.speed = other.speed; }
+        if other.quirks.is_some() { self.quirks = other.quirks.clone(); }
+        if other.vf_reset.is_some() { self.vf_reset = other.vf_reset; }
+        if other.shift_vy.is_some() { self.shift_vy = other.shift_vy; }
+        if other.memory_increment.is_some() { self.memory_increment = other.memory_increment; }
+        if other.jump_vx.is_some() { self.jump_vx = other.jump_vx; }
+        if other.clip.is_some() { self.clip = other.clip; }
+        if other.seed.is_some() { self.seed = other.seed; }
+        if other.mute.is_some() { self.mute = other.mute; }
+        if other.tone.is_some() { self.tone = other.tone; }
+        if other.waveform.is_some() { self.waveform = other.waveform.clone(); }
+        if other.volume.is_some() { self.volume = other.volume; }
+        if other.attack.is_some() { self.attack = other.attack; }
+        if other.release.is_some() { self.release = other.release; }
+        if other.fullscreen.is_some() { self.fullscreen = other.fullscreen; }
+        if other.screenshot_dir.is_some() { self.screenshot_dir = other.screenshot_dir.clone(); }
+        if other.record_format.is_some() { self.record_format = other.record_format.clone(); }
+        if other.terminal_mode.is_some() { self.terminal_mode = other.terminal_mode.clone(); }
+        self.keymap.extend(other.keymap.clone());
+    }
+}
+
+// ~/.config/chip8/config.toml
+//
+// scale = 16
+// theme = "amber"
+// foreground = "#33FF66"
+// quirks = "vip"
+//
+// [keymap]
+// "5" = ["W", "Up"]
+//
+// [rom."pong.ch8"]
+// speed = 8
+//
+// [rom."pong.ch8".keymap]
+// "1" = ["Up"]
+// "4" = ["Down"]
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct Config {
+    #[serde(flatten)]
+    pub settings: Settings,
+    pub rom: BTreeMap<String, Settings>
+}
+
+// Final settings after merging defaults, config file and command line
+#[derive(Debug)]
+pub struct Options {
+    pub rom: PathBuf,
+    pub scale: usize,
+    pub scaling: Scaling,
+    pub palette: Palette,
+    pub filter: FilterMode,
+    pub speed: usize,
+    pub quirks: Quirks,
+    pub seed: Option<u64>,
+    pub headless: bool,
+    pub mute: bool,
+    pub tone: Tone,
+    pub debug: bool,
+    pub fullscreen: bool,
+    pub frames: Option<u64>,
+    pub screenshot_dir: PathBuf,
+    pub record_format: String,
+    pub record: Option<PathBuf>,
+    pub wav: Option<PathBuf>, // Beeper rendered from the emulated frames
+    pub terminal: Option<TerminalMode>, // Draw in the terminal instead of a window
+    pub trace: Option<PathBuf>, // Instruction log
+    pub trace_range: Option<(usize, usize)>, // PC range logged
+    pub compare: Option<PathBuf>, // Reference trace to check against
+    pub movie: Option<PathBuf>, // Input for the comparison
+    pub profile: Option<PathBuf>, // Profiler report
+    pub keymap: Keymap
+}
+
+impl Config {
+    // Load the config file, a missing file gives the defaults
+    pub fn load() -> Result<Config, String> {
+        match config_path() {
+            Some(path) if path.exists() => Config::load_from(&path),
+            _ => Ok(Config::default())
+        }
+    }
+
+    pub fn load_from(path: &Path) -> Result<Config, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(v) => v,
+            Err(err) => {return Err(format!("{}: {}", path.display(), err));}
+        };
+
+        Config::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    // Config file contents, unknown settings in any section are an error
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let config: Config = toml::from_str(text).map_err(|err| err.to_string())?;
+
+        config.settings.check()?;
+        for (rom, settings) in &config.rom {
+            settings.check().map_err(|err| format!("{} in [rom.\"{}\"]", err, rom))?;
+        }
+
+        Ok(config)
+    }
+
+    // Section for a ROM, looked up by file name
+    pub fn rom_settings(&self, rom: &Path) -> Option<&Settings> {
+        let name = rom.file_name()?.to_str()?;
+        self.rom.get(name)
+    }
+
+    // Global section, then the ROM database entry, then the ROM section,
+    // then the command line
+    pub fn options(&self, args: Args, database: Option<&Settings>) -> Result<Options, String> {
+        let mut settings = self.settings.clone();
+        if let Some(database) = database {
+            settings.merge(database);
+        }
+        if let Some(rom_settings) = self.rom_settings(&args.rom) {
+            settings.merge(rom_settings);
+        }
+        settings.merge(&args.settings);
+
+        let scale = settings.scale.unwrap_or(DEFAULT_SCALE);
+        if !(1..=64).contains(&scale) {
+            return Err(format!("Invalid scale {}, expected 1 to 64", scale));
+        }
+
+        let scaling = match &settings.scaling {
+            Some(name) => match Scaling::parse(name) {
+                Some(s) => s,
+                None => {return Err(format!("Unknown scaling '{}'", name));}
+            },
+            None => Scaling::Integer
+        };
+
+        let speed = settings.speed.unwrap_or(DEFAULT_SPEED);
+        if speed == 0 {
+            return Err(String::from("Speed must be at least 1 instruction per frame"));
+        }
+
+        let mut tone = Tone::default();
+        if let Some(frequency) = settings.tone {
+            if !(20.0..=20000.0).contains(&frequency) {
+                return Err(format!("Invalid tone {} Hz, expected 20 to 20000", frequency));
+            }
+            tone.frequency = frequency;
+        }
+        if let Some(name) = &settings.waveform {
+            tone.waveform = match Waveform::parse(name) {
+                Some(w) => w,
+                None => {return Err(format!("Unknown waveform '{}', expected square, sine, triangle or sawtooth", name));}
+            };
+        }
+        if let Some(volume) = settings.volume {
+            if !(0.0..=1.0).contains(&volume) {
+                return Err(format!("Invalid volume {}, expected 0 to 1", volume));
+            }
+            tone.volume = volume;
+        }
+        for (value, duration, name) in [(settings.attack, &mut tone.attack, "attack"), (settings.release, &mut tone.release, "release")] {
+            if let Some(ms) = value {
+                if !(0.0..=1000.0).contains(&ms) {
+                    return Err(format!("Invalid {} {} ms, expected 0 to 1000", name, ms));
+                }
+                *duration = std::time::Duration::from_secs_f32(ms / 1000.0);
+            }
+        }
+
+        let mut quirks = match &settings.quirks {
+            Some(name) => match Quirks::preset(name) {
+                Some(q) => q,
+                None => {return Err(format!("Unknown quirk preset '{}'", name));}
+            },
+            None => Quirks::default()
+        };
+        if let Some(v) = settings.vf_reset { quirks.vf_reset = v; }
+        if let Some(v) = settings.shift_vy { quirks.shift_vy = v; }
+        if let Some(v) = settings.memory_increment { quirks.memory_increment = v; }
+        if let Some(v) = settings.jump_vx { quirks.jump_vx = v; }
+        if let Some(clip) = settings.clip {
+            quirks.sprite_mode = if clip { SpriteMode::Clip } else { SpriteMode::Wrap };
+        }
+
+        let mut palette = match &settings.theme {
+            Some(name) => match Palette::theme(name) {
+                Some(p) => p,
+                None => {return Err(format!("Unknown theme '{}'", name));}
+            },
+            None => Palette::default()
+        };
+        if let Some(colors) = &settings.palette {
+            if ![2, 4, 16].contains(&colors.len()) {
+                return Err(format!("Palette has {} colours, expected 2, 4 or 16", colors.len()));
+            }
+            palette = Palette {
+                name: String::from("custom"),
+                colors: colors.iter().map(|c| color(c)).collect::<Result<_, _>>()?
+            };
+        }
+        if let Some(c) = &settings.background {
+            palette.colors[0] = color(c)?;
+            palette.name = String::from("custom");
+        }
+        if let Some(c) = &settings.foreground {
+            palette.colors[1] = color(c)?;
+            palette.name = String::from("custom");
+        }
+
+        let persistence = settings.persistence.unwrap_or(DEFAULT_PERSISTENCE);
+        // 1.0 would keep erased pixels lit forever
+        if !(0.0..1.0).contains(&persistence) {
+            return Err(format!("Invalid persistence {}, expected at least 0.0 and below 1.0", persistence));
+        }
+        let filter = match &settings.filter {
+            Some(name) => match FilterMode::parse(name, persistence) {
+                Some(f) => f,
+                None => {return Err(format!("Unknown filter '{}'", name));}
+            },
+            None => FilterMode::None
+        };
+
+        let record_format = settings.record_format.unwrap_or(String::from("gif")).to_ascii_lowercase();
+        if record_format != "gif" && record_format != "y4m" {
+            return Err(format!("Unknown recording format '{}', expected gif or y4m", record_format));
+        }
+
+        let terminal_mode = match &settings.terminal_mode {
+            Some(name) => match TerminalMode::parse(name) {
+                Some(m) => m,
+                None => {return Err(format!("Unknown terminal mode '{}', expected halfblock or braille", name));}
+            },
+            None => TerminalMode::HalfBlock
+        };
+
+        let mut keymap = Keymap::default();
+        keymap.apply(&settings.keymap)?;
+
+        let trace_range = match &args.trace_range {
+            Some(text) => match trace::parse_range(text) {
+                Some(r) => Some(r),
+                None => {return Err(format!("Invalid trace range '{}', expected hex addresses like 200-2FF", text));}
+            },
+            None => None
+        };
+        if args.movie.is_some() && args.compare.is_none() {
+            return Err(String::from("--movie is only used with --compare"));
+        }
+
+        Ok(Options {
+            rom: args.rom,
+            scale,
+            scaling,
+            palette,
+            filter,
+            speed,
+            quirks,
+            seed: settings.seed,
+            headless: args.headless,
+            mute: settings.mute.unwrap_or(false),
+            tone,
+            debug: args.debug,
+            fullscreen: settings.fullscreen.unwrap_or(false),
+            frames: args.frames,
+            screenshot_dir: settings.screenshot_dir.unwrap_or(PathBuf::from(".")),
+            record_format,
+            record: args.record,
+            wav: args.wav,
+            terminal: if args.terminal { Some(terminal_mode) } else { None },
+            trace: args.trace,
+            trace_range,
+            compare: args.compare,
+            movie: args.movie,
+            profile: args.profile,
+            keymap
+        })
+    }
+}
+
+// $XDG_CONFIG_HOME/chip8/config.toml, falling back to ~/.config
+pub fn config_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?).join(".config")
+    };
+
+    Some(base.join("chip8").join("config.toml"))
+}
+
+// "#RRGGBB" or "RRGGBB" to 0xRRGGBB
+pub fn parse_color(text: &str) -> Option<u32> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+fn color(text: &str) -> Result<u32, String> {
+    parse_color(text).ok_or(format!("Invalid colour '{}', expected #RRGGBB", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_settings() {
+        let config = Config::parse("scale = 8\ntone = 440\npersistence = 0.5\n[keymap]\n\"5\" = [\"W\"]\n[rom.\"pong.ch8\"]\nspeed = 8\n").unwrap();
+        assert_eq!(config.settings.scale, Some(8));
+        assert_eq!(config.settings.tone, Some(440.0));
+        assert_eq!(config.settings.keymap["5"], vec![String::from("W")]);
+        assert_eq!(config.rom["pong.ch8"].speed, Some(8));
+    }
+
+    #[test]
+    fn unknown_settings() {
+        assert_eq!(Config::parse("quirk = \"vip\"\n").unwrap_err(), "Unknown setting 'quirk'");
+        assert_eq!(Config::parse("[rom.\"pong.ch8\"]\nsped = 8\n").unwrap_err(), "Unknown setting 'sped' in [rom.\"pong.ch8\"]");
+
+        let settings: Settings = serde_json::from_str(r#"{ "speed": 8, "seeed": 1 }"#).unwrap();
+        assert!(settings.check().is_err());
+    }
+}
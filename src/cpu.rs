@@ -1,22 +1,33 @@
-use minifb::Key;
-use rand::{self, Rng};
+use rand::{Rng, SeedableRng};
+// The generator behind rand's StdRng, used directly because its position
+// can be saved and restored
+use rand_chacha::ChaCha12Rng;
 
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
+#[cfg(feature = "native")]
 use std::time::{Duration, Instant};
 
-
+#[cfg(feature = "native")]
 use crate::audio::Audio;
-use crate::window::Window;
+use crate::display::{self, Display};
+use crate::quirks::Quirks;
+#[cfg(feature = "native")]
+use crate::frontend::Frontend;
+use crate::profile::{Access, Profiler};
+use crate::trace::{Registers, TraceEntry, Tracer};
 
 // CPU Structure
-const RAM_SIZE: usize = 4096;
+pub(crate) const RAM_SIZE: usize = 4096;
 const REGISTER_COUNT: usize = 16;
 const STACK_SIZE: usize = 16;
-const PROGRAM_START: usize = 0x200;
+const I_MASK: usize = 0xFFFF; // I is a 16 bit register, it can point past RAM
+pub(crate) const PROGRAM_START: usize = 0x200;
+#[cfg(feature = "native")]
 const RUNLOOP_TIMER: Duration = Duration::from_micros(16667); //~60fps
-const INSTRUCTIONS_PER_FRAME: usize = 12;
+const INSTRUCTIONS_PER_FRAME: usize = 12; // Default speed
+
+// Save state header, bumped when the layout changes
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+const STATE_VERSION: u8 = 2;
 
 const FONTSET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -50,17 +61,32 @@ pub struct CPU {
     sp: usize, // Stack pointer
     // Stack
     stack: [usize; STACK_SIZE],
-    // Window and Audio
-    win: Window,
-    audio: Audio,
+    // Screen
+    display: Display,
     // Keys
     keypad: [bool; 16],
-    rng: rand::rngs::ThreadRng
+    key_wait: Option<usize>, // Key pressed during FX0A, waiting for release
+    rng: ChaCha12Rng,
+    // Settings
+    quirks: Quirks,
+    instructions_per_frame: usize,
+    frame_limit: Option<u64>,
+    frames: u64,
+    cycles: u64, // Instructions executed
+    frame_cycles: usize, // Instructions executed in the current frame
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>
+}
+
+impl Default for CPU {
+    fn default() -> CPU {
+        CPU::new()
+    }
 }
 
 impl CPU {
-    // New CPU
-    pub fn new(win: Window, audio: Audio) -> CPU {
+    // New CPU, the random generator starts from seed 0 until seed_rng
+    pub fn new() -> CPU {
 
         let mut new_cpu = CPU {
             ram: [0; RAM_SIZE],
@@ -71,66 +97,380 @@ impl CPU {
             st: 0,
             sp: 0,
             stack: [0; STACK_SIZE],
-            win, audio,
+            display: Display::new(),
             keypad: [false; 16],
-            rng: rand::rng()
+            key_wait: None,
+            rng: ChaCha12Rng::seed_from_u64(0),
+            quirks: Quirks::default(),
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            frame_limit: None,
+            frames: 0,
+            cycles: 0,
+            frame_cycles: 0,
+            tracer: None,
+            profiler: None
         };
         new_cpu.preload_ram();
 
         new_cpu
     }
 
+    // Settings
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.display.set_sprite_mode(quirks.sprite_mode);
+        self.quirks = quirks;
+    }
+
+    pub fn set_speed(&mut self, instructions_per_frame: usize) {
+        self.instructions_per_frame = instructions_per_frame;
+    }
+
+    pub fn set_frame_limit(&mut self, frames: Option<u64>) {
+        self.frame_limit = frames;
+    }
+
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = ChaCha12Rng::seed_from_u64(seed);
+    }
+
+    // Record every instruction, None stops tracing
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    // Count executions and memory use per address, None stops profiling
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    // Recent instructions leading to a fault from run_frame, when tracing
+    pub fn fault_dump(&self, error: &str) -> Option<String> {
+        let opcode = match (self.ram.get(self.pc), self.ram.get(self.pc + 1)) {
+            (Some(&high), Some(&low)) => Some((high as u16) << 8 | low as u16),
+            _ => None
+        };
+        Some(self.tracer.as_ref()?.dump(error, self.pc, opcode))
+    }
+
     fn preload_ram(&mut self) {
         self.ram[0..FONTSET.len()].copy_from_slice(&FONTSET);
     }
 
-    // Load ROM file
-    pub fn load_rom<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
-        // Read file and write to buffer RAM
-        let mut file = File::open(path)?;
-        file.read(&mut self.ram[0x200..])?;
+    // Write ROM bytes to RAM
+    pub fn load_program(&mut self, rom: &[u8]) -> std::io::Result<()> {
+        if rom.len() > RAM_SIZE - PROGRAM_START {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "ROM does not fit in memory"));
+        }
+        self.ram[PROGRAM_START..PROGRAM_START + rom.len()].copy_from_slice(rom);
         Ok(())
     }
 
-    // Run loop
-    pub fn run_loop(&mut self) -> Result<(), &str> {
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    // Frames emulated since the start
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Instructions executed since the start
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    // V registers, I, SP and the timers
+    pub fn registers(&self) -> Registers {
+        Registers { v: self.v, i: self.i, sp: self.sp, dt: self.dt, st: self.st }
+    }
+
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.v[x] = value;
+    }
+
+    // Everything that changes while running: memory, registers, timers,
+    // screen, FX0A wait, frame and cycle counts and the random generator position
+    // Settings (quirks, speed) are not included
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(RAM_SIZE + 256 + self.display.pixels().len());
+        state.extend_from_slice(STATE_MAGIC);
+        state.push(STATE_VERSION);
+
+        state.extend_from_slice(&self.ram);
+        state.extend_from_slice(&self.v);
+        state.extend_from_slice(&(self.i as u16).to_le_bytes());
+        state.extend_from_slice(&(self.pc as u16).to_le_bytes());
+        state.push(self.dt);
+        state.push(self.st);
+        state.push(self.sp as u8);
+        for address in self.stack {
+            state.extend_from_slice(&(address as u16).to_le_bytes());
+        }
+        state.push(self.key_wait.map_or(0xFF, |k| k as u8));
+        state.extend_from_slice(&self.frames.to_le_bytes());
+        state.extend_from_slice(&self.cycles.to_le_bytes());
+
+        state.extend_from_slice(&self.rng.get_seed());
+        state.extend_from_slice(&self.rng.get_stream().to_le_bytes());
+        state.extend_from_slice(&self.rng.get_word_pos().to_le_bytes());
+
+        state.extend_from_slice(&(self.display.width() as u16).to_le_bytes());
+        state.extend_from_slice(&(self.display.height() as u16).to_le_bytes());
+        state.extend_from_slice(self.display.pixels());
+
+        state
+    }
+
+    // Restore a save_state, the machine is left untouched on error
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let mut reader = StateReader { data: state };
+
+        if reader.take(4)? != STATE_MAGIC {
+            return Err(String::from("Not a save state"));
+        }
+        let version = reader.u8()?;
+        if version != STATE_VERSION {
+            return Err(format!("Unsupported save state version {}", version));
+        }
+
+        let mut ram = [0; RAM_SIZE];
+        ram.copy_from_slice(reader.take(RAM_SIZE)?);
+        let mut v = [0; REGISTER_COUNT];
+        v.copy_from_slice(reader.take(REGISTER_COUNT)?);
+        let i = reader.u16()? as usize;
+        let pc = reader.u16()? as usize;
+        let dt = reader.u8()?;
+        let st = reader.u8()?;
+        let sp = reader.u8()? as usize;
+        let mut stack = [0; STACK_SIZE];
+        for address in stack.iter_mut() {
+            *address = reader.u16()? as usize;
+        }
+        let key_wait = match reader.u8()? {
+            0xFF => None,
+            key => Some(key as usize)
+        };
+        let frames = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+        let cycles = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+
+        let mut seed = [0; 32];
+        seed.copy_from_slice(reader.take(32)?);
+        let stream = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+        let word_pos = u128::from_le_bytes(reader.take(16)?.try_into().unwrap());
+
+        let width = reader.u16()? as usize;
+        let height = reader.u16()? as usize;
+        if !display::SIZES.contains(&(width, height)) {
+            return Err(format!("Unsupported screen size {}x{} in save state", width, height));
+        }
+        let pixels = reader.take(width * height)?;
+
+        // Any I is fine, check_opcode guards the accesses through it
+        if pc >= RAM_SIZE || sp > STACK_SIZE || key_wait.is_some_and(|k| k > 0xF)
+            || stack.iter().any(|&address| address >= RAM_SIZE) {
+            return Err(String::from("Corrupt save state"));
+        }
+
+        self.ram = ram;
+        self.v = v;
+        self.i = i;
+        self.pc = pc;
+        self.dt = dt;
+        self.st = st;
+        self.sp = sp;
+        self.stack = stack;
+        self.key_wait = key_wait;
+        self.frames = frames;
+        self.cycles = cycles;
+        self.frame_cycles = 0;
+
+        self.rng = ChaCha12Rng::from_seed(seed);
+        self.rng.set_stream(stream);
+        self.rng.set_word_pos(word_pos);
+
+        self.display.restore(width, height, pixels);
+
+        Ok(())
+    }
+
+    // Emulate one 60Hz frame with the given keypad state
+    // Returns whether the sound timer ran during it
+    // Stops at the faulting instruction, PC still points at it
+    pub fn run_frame(&mut self, keypad: [bool; 16]) -> Result<bool, &'static str> {
+        loop {
+            if let Some(beeping) = self.step(keypad)? {
+                return Ok(beeping);
+            }
+        }
+    }
+
+    // Run a single instruction, the frame ends (timers tick) once the
+    // speed's worth of instructions has run
+    // Returns whether the sound timer ran when the frame ended, else None
+    pub fn step(&mut self, keypad: [bool; 16]) -> Result<Option<bool>, &'static str> {
+        self.keypad = keypad;
+
+        if self.frame_cycles < self.instructions_per_frame {
+            self.emulate_cycle()?;
+            self.frame_cycles += 1;
+        }
+        if self.frame_cycles < self.instructions_per_frame {
+            return Ok(None);
+        }
+
+        let beeping = self.st > 0;
+        self.update_timers();
+        self.frames += 1;
+        self.frame_cycles = 0;
+
+        Ok(Some(beeping))
+    }
+
+    // Run loop, paced at 60 frames per second
+    #[cfg(feature = "native")]
+    pub fn run_loop(&mut self, win: &mut dyn Frontend, audio: &mut Audio) -> Result<(), &'static str> {
         let mut current_time = Instant::now();
 
-        while self.win.is_open() {
-            // Break out of the loop
-            if self.win.is_key_down(Key::Escape) {
+        while win.is_open() {
+            // Stop after the requested number of frames
+            if self.frame_limit.is_some_and(|limit| self.frames >= limit) {
                 break;
             }
 
-            // Check for system errors
-            if self.pc > RAM_SIZE {
-                return Err("Error: Address out of bounds");
-            } else if self.sp > STACK_SIZE {
-                return Err("Error: Stack overflow")
+            if current_time.elapsed() > RUNLOOP_TIMER {
+
+                win.handle_hotkeys();
+                let keypad = win.handle_key_events();
+
+                // No frames reach the beeper while paused, so it fades out
+                let beeping = if win.paused() {
+                    false
+                } else {
+                    let beeping = self.run_frame(keypad)?;
+                    audio.frame(beeping);
+                    beeping
+                };
+                win.refresh(&self.display, beeping, self.frames);
+
+                current_time = Instant::now();
+            } else {
+                // Don't spin while waiting for the next frame
+                std::thread::sleep(Duration::from_millis(1));
             }
+        }
 
+        Ok(())
+    }
 
-            if current_time.elapsed() > RUNLOOP_TIMER {
+    // Registers and timers, for debugging
+    pub fn dump_state(&self) -> String {
+        let mut state = format!("frame: {}  PC: {:#05X}  I: {:#05X}  SP: {}  DT: {}  ST: {}\n",
+            self.frames, self.pc, self.i, self.sp, self.dt, self.st);
+
+        for (index, value) in self.v.iter().enumerate() {
+            state += &format!("V{:X}: {:#04X}{}", index, value, if index % 8 == 7 { "\n" } else { "  " });
+        }
 
-                self.keypad = self.win.handle_key_events();
+        state += &format!("stack: {:03X?}", &self.stack[..self.sp.min(STACK_SIZE)]);
+        state
+    }
 
-                for _ in 0..INSTRUCTIONS_PER_FRAME {
-                    self.emulate_cycle();
+    fn emulate_cycle(&mut self) -> Result<(), &'static str> {
+        // Check for system errors
+        if self.pc + 1 >= RAM_SIZE {
+            return Err("Error: Address out of bounds");
+        }
+        let opcode = self.fetch_opcode();
+        self.check_opcode(opcode)?;
+
+        if self.profiler.is_some() {
+            let (pc, access) = (self.pc, self.memory_access(opcode));
+            if let Some(profiler) = &mut self.profiler {
+                profiler.execute(pc);
+                if let Some((kind, start, len)) = access {
+                    profiler.access(pc, kind, start, len);
                 }
+            }
+        }
 
-                self.update_timers();
-                self.win.refresh();
+        // Only snapshot the registers when someone is looking
+        let traced = self.tracer.is_some().then(|| (self.pc, self.registers()));
+        self.execute_opcode(opcode);
 
-                current_time = Instant::now();
+        if let Some((pc, before)) = traced {
+            let entry = TraceEntry {
+                cycle: self.cycles,
+                frame: self.frames,
+                pc,
+                opcode,
+                before,
+                after: self.registers()
+            };
+            if let Some(tracer) = &mut self.tracer {
+                tracer.record(entry);
             }
         }
 
+        self.cycles += 1;
         Ok(())
     }
 
-    fn emulate_cycle(&mut self) {
-        let opcode = self.fetch_opcode();
-        self.execute_opcode(opcode);
+    // Memory an instruction reads or writes through I
+    fn memory_access(&self, opcode: u16) -> Option<(Access, usize, usize)> {
+        let x = ((opcode & 0x0F00) >> 8) as usize;
+
+        match (opcode & 0xF000, opcode & 0x00FF) {
+            (0xD000, _) => Some((Access::Read, self.i, (opcode & 0x000F) as usize)),
+            (0xF000, 0x33) => Some((Access::Write, self.i, 3)),
+            (0xF000, 0x55) => Some((Access::Write, self.i, x + 1)),
+            (0xF000, 0x65) => Some((Access::Read, self.i, x + 1)),
+            _ => None
+        }
+    }
+
+    // Instructions that would leave memory, the stack or the keypad
+    fn check_opcode(&self, opcode: u16) -> Result<(), &'static str> {
+        let x = ((opcode & 0x0F00) >> 8) as usize;
+        let n = (opcode & 0x000F) as usize;
+
+        let (fault, error) = match opcode & 0xF000 {
+            0x0000 if opcode == 0x00EE => (self.sp == 0, "Error: Stack underflow"),
+            0x2000 => (self.sp >= STACK_SIZE, "Error: Stack overflow"),
+            0xD000 => (self.i + n > RAM_SIZE, "Error: Sprite out of bounds"),
+            0xE000 if matches!(opcode & 0x00FF, 0x9E | 0xA1) => (self.v[x] > 0xF, "Error: Invalid key"),
+            0xF000 => match opcode & 0x00FF {
+                0x33 => (self.i + 3 > RAM_SIZE, "Error: Memory access out of bounds"),
+                0x55 | 0x65 => (self.i + x + 1 > RAM_SIZE, "Error: Memory access out of bounds"),
+                _ => (false, "")
+            },
+            _ => (false, "")
+        };
+
+        if fault { Err(error) } else { Ok(()) }
     }
 
     fn fetch_opcode(&mut self) -> u16 {
@@ -148,10 +488,6 @@ impl CPU {
 
         if self.st > 0 {
             self.st -= 1;
-            self.audio.play();
-
-        } else {
-            self.audio.pause();
         }
 
     }
@@ -206,7 +542,7 @@ impl CPU {
 
     // Clear the display
     fn op_00e0(&mut self) {
-        self.win.clear_screen();
+        self.display.clear();
         self.pc += 2;
     }
 
@@ -290,6 +626,9 @@ impl CPU {
         let x = ((opcode & 0x0F00) >> 8) as usize;
         let y = ((opcode & 0x00F0) >> 4) as usize;
         self.v[x] |= self.v[y];
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
         self.pc += 2;
     }
 
@@ -298,6 +637,9 @@ impl CPU {
         let x = ((opcode & 0x0F00) >> 8) as usize;
         let y = ((opcode & 0x00F0) >> 4) as usize;
         self.v[x] &= self.v[y];
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
         self.pc += 2;
     }
 
@@ -306,6 +648,9 @@ impl CPU {
         let x = ((opcode & 0x0F00) >> 8) as usize;
         let y = ((opcode & 0x00F0) >> 4) as usize;
         self.v[x] ^= self.v[y];
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
         self.pc += 2;
     }
 
@@ -329,11 +674,13 @@ impl CPU {
         self.pc += 2;
     }
 
-    // Set Vx = Vx SHR 1.
+    // Set Vx = Vx SHR 1 (or Vy SHR 1)
     fn op_8xy6(&mut self, opcode: u16) {
         let x = ((opcode & 0x0F00) >> 8) as usize;
-        self.v[0xF] = self.v[x] & 0x01;
-        self.v[x] >>= 1;
+        let y = ((opcode & 0x00F0) >> 4) as usize;
+        let value = if self.quirks.shift_vy { self.v[y] } else { self.v[x] };
+        self.v[x] = value >> 1;
+        self.v[0xF] = value & 0x01;
         self.pc += 2;
     }
 
@@ -347,11 +694,13 @@ impl CPU {
         self.pc += 2;
     }
 
-    // Set Vx = Vx SHL 1
+    // Set Vx = Vx SHL 1 (or Vy SHL 1)
     fn op_8xye(&mut self, opcode: u16) {
         let x = ((opcode & 0x0F00) >> 8) as usize;
-        self.v[0xF] = (self.v[x] & 0x80) >> 7;
-        self.v[x] <<= 1;
+        let y = ((opcode & 0x00F0) >> 4) as usize;
+        let value = if self.quirks.shift_vy { self.v[y] } else { self.v[x] };
+        self.v[x] = value << 1;
+        self.v[0xF] = (value & 0x80) >> 7;
         self.pc += 2;
     }
 
@@ -373,10 +722,11 @@ impl CPU {
         self.pc += 2;
     }
 
-    // Jump to location nnn + V0
+    // Jump to location nnn + V0 (or xnn + Vx)
     fn op_bnnn(&mut self, opcode: u16) {
         let nnn = (opcode & 0x0FFF) as usize;
-        self.pc = nnn + self.v[0] as usize;
+        let x = if self.quirks.jump_vx { ((opcode & 0x0F00) >> 8) as usize } else { 0 };
+        self.pc = nnn + self.v[x] as usize;
     }
 
     // Set Vx = random byte AND kk
@@ -397,13 +747,9 @@ impl CPU {
         let vx = self.v[x];
         let vy = self.v[y];
 
-        let mut bytes_to_draw: Vec<u8> = Vec::new();
+        let bytes_to_draw = &self.ram[self.i..self.i + height];
 
-        for i in 0..height {
-            bytes_to_draw.push(self.ram[self.i + i]);
-        }
-
-        self.v[0xF] = self.win.draw(&bytes_to_draw, vx, vy);
+        self.v[0xF] = self.display.draw(bytes_to_draw, vx, vy);
         self.pc += 2;
     }
 
@@ -434,18 +780,24 @@ impl CPU {
         self.pc += 2;
     }
 
-    // Wait for a key press, store the value of the key in Vx
+    // Wait for a key press and release, store the value of the key in Vx
+    // PC is left untouched while waiting, so the instruction runs again
+    // next cycle and the timers keep ticking in the meantime
     fn op_fx0a(&mut self, opcode: u16) {
         let x = ((opcode & 0x0F00) >> 8) as usize;
 
-        let key_pressed = self.keypad.iter().position(|&k| k);
-        match key_pressed {
+        match self.key_wait {
+            // Key is down, complete once it is released
             Some(key) => {
-                self.v[x] = key as u8;
-                self.pc += 2;
+                if !self.keypad[key] {
+                    self.v[x] = key as u8;
+                    self.key_wait = None;
+                    self.pc += 2;
+                }
             }
+            // Wait for any key to be pressed
             None => {
-                self.pc -= 2
+                self.key_wait = self.keypad.iter().position(|&k| k);
             }
         }
     }
@@ -467,7 +819,7 @@ impl CPU {
     // Set I = I + Vx
     fn op_fx1e(&mut self, opcode: u16) {
         let x = ((opcode & 0x0F00) >> 8) as usize;
-        self.i = self.i.wrapping_add(self.v[x] as usize);
+        self.i = (self.i + self.v[x] as usize) & I_MASK;
         self.pc += 2;
     }
 
@@ -496,6 +848,9 @@ impl CPU {
         for index in 0..=x as usize {
             self.ram[self.i+ index] = self.v[index];
         }
+        if self.quirks.memory_increment {
+            self.i = (self.i + x as usize + 1) & I_MASK;
+        }
         self.pc += 2;
     }
 
@@ -506,8 +861,191 @@ impl CPU {
         for i in 0..=x as usize {
             self.v[i] = self.ram[self.i + i];
         }
+        if self.quirks.memory_increment {
+            self.i = (self.i + x as usize + 1) & I_MASK;
+        }
 
         self.pc += 2;
     }
 }
 
+// Reads a save state front to back
+struct StateReader<'a> {
+    data: &'a [u8]
+}
+
+impl<'a> StateReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err(String::from("Truncated save state"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes([self.take(1)?[0], self.take(1)?[0]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RND V0, 0x0F / LD F, V0 / DRW V0, V1, 5 / LD ST, V0 / CALL 0x20C / JP 0x200 / RET
+    const ROM: [u8; 14] = [0xC0, 0x0F, 0xF0, 0x29, 0xD0, 0x15, 0xF0, 0x18, 0x22, 0x0C, 0x12, 0x00, 0x00, 0xEE];
+
+    fn machine() -> CPU {
+        let mut cpu = CPU::new();
+        cpu.seed_rng(7);
+        cpu.set_speed(10);
+        cpu.load_program(&ROM).unwrap();
+        cpu
+    }
+
+    // Offsets in a save state
+    const PC: usize = 4 + 1 + RAM_SIZE + REGISTER_COUNT + 2;
+    const STACK: usize = PC + 2 + 3;
+
+    fn width_offset(state: &[u8]) -> usize {
+        state.len() - display::WIDTH * display::HEIGHT - 4
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let mut cpu = machine();
+        for _ in 0..5 {
+            cpu.run_frame([false; 16]).unwrap();
+        }
+        let state = cpu.save_state();
+
+        let mut copy = CPU::new();
+        copy.set_speed(10);
+        copy.load_state(&state).unwrap();
+        assert_eq!(copy.save_state(), state);
+
+        // Same random numbers and screen from here on
+        for _ in 0..5 {
+            assert_eq!(cpu.run_frame([false; 16]), copy.run_frame([false; 16]));
+        }
+        assert_eq!(cpu.save_state(), copy.save_state());
+    }
+
+    #[test]
+    fn state_with_i_past_ram() {
+        // LD I, 0xFFF / LD V0, 0xFF / ADD I, V0 / JP 0x204
+        let mut cpu = CPU::new();
+        cpu.load_program(&[0xAF, 0xFF, 0x60, 0xFF, 0xF0, 0x1E, 0x12, 0x04]).unwrap();
+        for _ in 0..600 {
+            cpu.step([false; 16]).unwrap();
+        }
+        // 299 adds of 0xFF, wrapped to 16 bits
+        assert_eq!(cpu.registers().i, (0xFFF + 0xFF * 299) & 0xFFFF);
+
+        let state = cpu.save_state();
+        let mut copy = CPU::new();
+        copy.load_state(&state).unwrap();
+        assert_eq!(copy.registers().i, cpu.registers().i);
+        assert_eq!(copy.save_state(), state);
+    }
+
+    #[test]
+    fn corrupt_states_rejected() {
+        let mut cpu = machine();
+        cpu.run_frame([false; 16]).unwrap();
+        let state = cpu.save_state();
+
+        let corrupt = |offset: usize, bytes: &[u8]| {
+            let mut state = state.clone();
+            state[offset..offset + bytes.len()].copy_from_slice(bytes);
+            state
+        };
+        let width = width_offset(&state);
+        let bad = [
+            Vec::new(),
+            state[..state.len() - 1].to_vec(),
+            corrupt(0, b"XXXX"),
+            corrupt(4, &[STATE_VERSION + 1]),
+            corrupt(PC, &0x1000u16.to_le_bytes()),
+            corrupt(STACK + 2, &0xFFFFu16.to_le_bytes()),
+            corrupt(width, &0u16.to_le_bytes()),
+            corrupt(width, &[0, 0, 0, 0]),
+            corrupt(width, &[32, 0, 64, 0])
+        ];
+
+        let mut target = machine();
+        let before = target.save_state();
+        for blob in &bad {
+            assert!(target.load_state(blob).is_err());
+            assert_eq!(target.save_state(), before);
+        }
+    }
+
+    // Run a ROM until it faults or steps run out
+    fn run(rom: &[u8], steps: usize) -> (CPU, Result<(), &'static str>) {
+        let mut cpu = CPU::new();
+        cpu.set_speed(1000);
+        cpu.load_program(rom).unwrap();
+        let result = (0..steps).try_for_each(|_| cpu.step([false; 16]).map(|_| ()));
+        (cpu, result)
+    }
+
+    #[test]
+    fn opcode_faults() {
+        // ROM, PC of the faulting instruction, error
+        let faults: [(&[u8], usize, &str); 8] = [
+            (&[0x00, 0xEE], 0x200, "Error: Stack underflow"),
+            (&[0x22, 0x00], 0x200, "Error: Stack overflow"),
+            (&[0xAF, 0xFF, 0xD0, 0x02], 0x202, "Error: Sprite out of bounds"),
+            (&[0x60, 0x10, 0xE0, 0x9E], 0x202, "Error: Invalid key"),
+            (&[0xAF, 0xFE, 0xF0, 0x33], 0x202, "Error: Memory access out of bounds"),
+            (&[0xAF, 0xFF, 0xF1, 0x55], 0x202, "Error: Memory access out of bounds"),
+            (&[0xAF, 0xFF, 0xF1, 0x65], 0x202, "Error: Memory access out of bounds"),
+            (&[0x1F, 0xFF], 0xFFF, "Error: Address out of bounds")
+        ];
+
+        for (rom, pc, error) in faults {
+            let (cpu, result) = run(rom, 20);
+            assert_eq!(result, Err(error), "{:02X?}", rom);
+            assert_eq!(cpu.pc(), pc, "{:02X?}", rom);
+        }
+    }
+
+    #[test]
+    fn opcodes_at_the_limits() {
+        let fine: [&[u8]; 5] = [
+            &[0xAF, 0xFF, 0xD0, 0x01],
+            &[0x60, 0x0F, 0xE0, 0x9E],
+            &[0xAF, 0xFD, 0xF0, 0x33],
+            &[0xAF, 0xFF, 0xF0, 0x55],
+            &[0xAF, 0xFF, 0xF0, 0x65]
+        ];
+
+        for rom in fine {
+            assert_eq!(run(rom, 2).1, Ok(()), "{:02X?}", rom);
+        }
+    }
+
+    #[test]
+    fn timers_tick_once_per_frame() {
+        // LD V0, 0x02 / LD DT, V0 / LD ST, V0 / JP 0x206
+        let mut cpu = CPU::new();
+        cpu.set_speed(5);
+        cpu.load_program(&[0x60, 0x02, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x06]).unwrap();
+
+        let mut ends = Vec::new();
+        for _ in 0..15 {
+            if let Some(beeping) = cpu.step([false; 16]).unwrap() {
+                ends.push((cpu.cycles(), cpu.frames(), cpu.registers().dt, beeping));
+            }
+        }
+
+        // Instructions run, frames, DT after the tick, sound timer on before it
+        assert_eq!(ends, [(5, 1, 1, true), (10, 2, 0, true), (15, 3, 0, false)]);
+    }
+}
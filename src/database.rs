@@ -0,0 +1,233 @@
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::config::{self, Settings};
+
+// Bundled copy of the community CHIP-8 database
+// https://github.com/chip-8/chip-8-database
+const PROGRAMS: &str = include_str!("../database/programs.json");
+const PLATFORMS: &str = include_str!("../database/platforms.json");
+
+// Quirk flags as named by the database
+// Partial sets are used for quirkyPlatforms overrides
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, rename_all = "camelCase")]
+struct PlatformQuirks {
+    shift: Option<bool>,
+    memory_increment_by_x: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    wrap: Option<bool>,
+    jump: Option<bool>,
+    vblank: Option<bool>,
+    logic: Option<bool>
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Platform {
+    id: String,
+    name: String,
+    default_tickrate: Option<usize>,
+    quirks: PlatformQuirks
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct Colors {
+    pixels: Vec<String> // Background first, then one per plane combination
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<usize>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    quirky_platforms: BTreeMap<String, PlatformQuirks>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8> // "up" -> chip8 key
+}
+
+#[derive(Deserialize, Debug)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    roms: BTreeMap<String, Rom> // Keyed by SHA-1
+}
+
+pub struct Database {
+    programs: Vec<Program>,
+    platforms: Vec<Platform>
+}
+
+// Database entry for a ROM
+#[derive(Debug)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub platform: String,
+    pub settings: Settings,
+    pub keys: BTreeMap<String, Vec<String>> // Host keys to add, in keymap table form
+}
+
+impl Database {
+    // Use ~/.config/chip8/database/*.json when present, else the bundled copy
+    pub fn load() -> Result<Database, String> {
+        let dir = config::config_path().and_then(|p| Some(p.parent()?.join("database")));
+
+        let read = |name: &str, bundled: &str| -> Result<String, String> {
+            match &dir {
+                Some(dir) if dir.join(name).exists() => {
+                    fs::read_to_string(dir.join(name)).map_err(|err| format!("{}: {}", name, err))
+                }
+                _ => Ok(String::from(bundled))
+            }
+        };
+
+        let programs = read("programs.json", PROGRAMS)?;
+        let platforms = read("platforms.json", PLATFORMS)?;
+
+        Ok(Database {
+            programs: serde_json::from_str(&programs).map_err(|err| format!("programs.json: {}", err))?,
+            platforms: serde_json::from_str(&platforms).map_err(|err| format!("platforms.json: {}", err))?
+        })
+    }
+
+    // Look up a ROM by the SHA-1 of its contents
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomInfo> {
+        let hash = sha1_hex(rom);
+
+        let (program, entry) = self.programs.iter()
+            .find_map(|p| p.roms.get(&hash).map(|r| (p, r)))?;
+
+        // First listed platform is the preferred one
+        let platform_id = entry.platforms.first()?;
+        let platform = self.platforms.iter().find(|p| &p.id == platform_id);
+
+        let mut quirks = platform.map(|p| p.quirks.clone()).unwrap_or_default();
+        if let Some(overrides) = entry.quirky_platforms.get(platform_id) {
+            quirks.merge(overrides);
+        }
+
+        let mut settings = quirks.settings();
+        settings.speed = entry.tickrate.or(platform.and_then(|p| p.default_tickrate));
+
+        if let Some(colors) = &entry.colors && [2, 4, 16].contains(&colors.pixels.len()) {
+            settings.palette = Some(colors.pixels.clone());
+        }
+
+        // Directional controls also go on the arrow keys, only for the
+        // chip8 keys the entry names
+        let mut keys: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (control, &chip8_key) in &entry.keys {
+            let host_key = match control.as_str() {
+                "up" => "Up",
+                "down" => "Down",
+                "left" => "Left",
+                "right" => "Right",
+                "a" => "Space",
+                _ => continue
+            };
+            if chip8_key < 16 {
+                keys.entry(format!("{:X}", chip8_key)).or_default().push(String::from(host_key));
+            }
+        }
+
+        Some(RomInfo {
+            title: program.title.clone(),
+            authors: program.authors.clone(),
+            platform: platform.map_or(platform_id.clone(), |p| p.name.clone()),
+            settings,
+            keys
+        })
+    }
+}
+
+impl PlatformQuirks {
+    fn merge(&mut self, other: &PlatformQuirks) {
+        if other.shift.is_some() { self.shift = other.shift; }
+        if other.memory_increment_by_x.is_some() { self.memory_increment_by_x = other.memory_increment_by_x; }
+        if other.memory_leave_i_unchanged.is_some() { self.memory_leave_i_unchanged = other.memory_leave_i_unchanged; }
+        if other.wrap.is_some() { self.wrap = other.wrap; }
+        if other.jump.is_some() { self.jump = other.jump; }
+        if other.vblank.is_some() { self.vblank = other.vblank; }
+        if other.logic.is_some() { self.logic = other.logic; }
+    }
+
+    // Map onto our quirk flags
+    // vblank is not emulated and I += x is treated as I += x + 1
+    fn settings(&self) -> Settings {
+        let leave_i = self.memory_leave_i_unchanged.unwrap_or(false);
+
+        Settings {
+            vf_reset: Some(self.logic.unwrap_or(false)),
+            shift_vy: Some(!self.shift.unwrap_or(false)),
+            memory_increment: Some(!leave_i),
+            jump_vx: Some(self.jump.unwrap_or(false)),
+            clip: Some(!self.wrap.unwrap_or(false)),
+            ..Settings::default()
+        }
+    }
+}
+
+fn sha1_hex(data: &[u8]) -> String {
+    Sha1::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundled() -> Database {
+        Database {
+            programs: serde_json::from_str(PROGRAMS).unwrap(),
+            platforms: serde_json::from_str(PLATFORMS).unwrap()
+        }
+    }
+
+    #[test]
+    fn known_hash_resolves() {
+        let db = bundled();
+        let (program, rom) = db.programs.iter()
+            .find_map(|p| p.roms.get("ea9af3c09b0d9e265fcd92bcc5d51a2939fdf27a").map(|r| (p, r)))
+            .unwrap();
+        assert_eq!(program.title, "15 Puzzle");
+        assert_eq!(rom.platforms[0], "originalChip8");
+    }
+
+    #[test]
+    fn every_platform_is_known() {
+        let db = bundled();
+        for program in &db.programs {
+            for rom in program.roms.values() {
+                for id in &rom.platforms {
+                    assert!(db.platforms.iter().any(|p| &p.id == id), "{}: unknown platform {}", program.title, id);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn only_listed_keys() {
+        let programs = r#"[{ "title": "Loop", "roms": { "92a5652d382a18e89c4881ec57041fc7d885ca80": {
+            "platforms": ["modernChip8"], "keys": { "up": 5, "down": 8, "player2Up": 1 } } } }]"#;
+        let db = Database { programs: serde_json::from_str(programs).unwrap(), platforms: serde_json::from_str(PLATFORMS).unwrap() };
+        let info = db.lookup(&[0x12, 0x00]).unwrap();
+
+        assert!(info.settings.keymap.is_empty());
+        assert_eq!(info.keys.len(), 2);
+        assert_eq!(info.keys["5"], vec![String::from("Up")]);
+        assert_eq!(info.keys["8"], vec![String::from("Down")]);
+    }
+
+    #[test]
+    fn unknown_rom() {
+        assert!(bundled().lookup(&[0x12, 0x00]).is_none());
+    }
+}
@@ -0,0 +1,128 @@
+// Logical chip8 screen
+// Each pixel holds a bitmask of the planes lit on it, the renderer
+// turns it into colours
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+// Sizes a save state may restore, lores and SCHIP hires
+pub const SIZES: [(usize, usize); 2] = [(WIDTH, HEIGHT), (128, 64)];
+
+// Plane drawn by Dxyn
+const PLANE_1: u8 = 0b01;
+
+// How sprites behave at the screen edges
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SpriteMode {
+    // Every pixel wraps around to the opposite side
+    Wrap,
+    // Only the starting coordinate wraps, the rest is clipped (VIP/SCHIP)
+    Clip
+}
+
+pub struct Display {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+    sprite_mode: SpriteMode
+}
+
+impl Default for Display {
+    fn default() -> Display {
+        Display::new()
+    }
+}
+
+impl Display {
+    pub fn new() -> Display {
+        Display {
+            width: WIDTH,
+            height: HEIGHT,
+            pixels: vec![0; WIDTH * HEIGHT],
+            sprite_mode: SpriteMode::Wrap
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Plane bitmask per pixel, row by row
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn set_sprite_mode(&mut self, mode: SpriteMode) {
+        self.sprite_mode = mode;
+    }
+
+    // Replace the whole screen, for save states
+    // The size must be one of SIZES, with width * height pixels
+    pub fn restore(&mut self, width: usize, height: usize, pixels: &[u8]) {
+        self.width = width;
+        self.height = height;
+        self.pixels = pixels.to_vec();
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.fill(0);
+    }
+
+    // XOR a sprite onto the screen, returns 1 if any pixel was erased
+    pub fn draw(&mut self, bytes: &[u8], init_x: u8, init_y: u8) -> u8 {
+
+        // 0 1 0 0 0 1 1 1
+        // 1 1 1 1 0 0 0 0
+        // etc
+
+        let mut vf: u8 = 0;
+
+        // The starting coordinate always wraps
+        let start_x = init_x as usize % self.width;
+        let start_y = init_y as usize % self.height;
+
+        // Loop bytes vector
+        for (vector_index, byte) in bytes.iter().enumerate() {
+            // Loop within each byte
+            for byte_index in 0..8 { //0-7
+
+                let mut x = start_x + byte_index;
+                let mut y = start_y + vector_index;
+
+                // Past the edge: wrap around or drop the pixel
+                if x >= self.width || y >= self.height {
+                    match self.sprite_mode {
+                        SpriteMode::Wrap => {
+                            x %= self.width;
+                            y %= self.height;
+                        }
+                        SpriteMode::Clip => continue
+                    }
+                }
+
+                let coord = (y * self.width) + x;
+
+                // Check if this bit is set in the sprite
+                let sprite_pixel_set = (byte & (1 << (7 - byte_index))) != 0;
+
+                // Only process if sprite pixel is set
+                if sprite_pixel_set {
+                    let display_pixel_on = self.pixels[coord] & PLANE_1 != 0;
+
+                    // XOR: toggle the pixel
+                    self.pixels[coord] ^= PLANE_1;
+
+                    // Set VF if we're turning off a pixel (collision)
+                    if display_pixel_on {
+                        vf = 1;
+                    }
+                }
+            }
+        }
+
+        vf
+    }
+}
@@ -0,0 +1,225 @@
+// Gym style environment over the core, for running many headless episodes
+//
+//     let config = EnvConfig {
+//         reward: Some(RamValue { address: 0x2F0, kind: ValueKind::Bcd(3) }),
+//         done: vec![Condition { value: RamValue { address: 0x2F5, kind: ValueKind::Byte }, compare: Compare::Equal, target: 0 }],
+//         frame_skip: 4,
+//         ..EnvConfig::default()
+//     };
+//     let mut env = Env::new(&rom, config)?;
+//     let mut observation = env.reset(1);
+//     loop {
+//         let (next, reward, done) = env.step(1 << 5);
+//         ...
+//     }
+//
+// Actions are keypad bitmasks, bit n held = chip8 key n down
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+
+use crate::cpu::CPU;
+use crate::quirks::Quirks;
+
+// How a number is stored in RAM
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ValueKind {
+    Byte,
+    // Big endian, like chip8 addresses
+    Word,
+    // One decimal digit per byte, most significant first, as written by Fx33
+    Bcd(usize)
+}
+
+// Number read from RAM, such as a score or a life counter
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RamValue {
+    pub address: usize,
+    pub kind: ValueKind
+}
+
+impl RamValue {
+    pub fn read(&self, ram: &[u8]) -> i64 {
+        let byte = |offset: usize| ram.get(self.address + offset).copied().unwrap_or(0) as i64;
+
+        match self.kind {
+            ValueKind::Byte => byte(0),
+            ValueKind::Word => byte(0) << 8 | byte(1),
+            ValueKind::Bcd(digits) => (0..digits).fold(0, |value, digit| value * 10 + byte(digit) % 10)
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Compare {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual
+}
+
+// Ends the episode when a RAM value compares true against target
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Condition {
+    pub value: RamValue,
+    pub compare: Compare,
+    pub target: i64
+}
+
+impl Condition {
+    pub fn holds(&self, ram: &[u8]) -> bool {
+        let value = self.value.read(ram);
+
+        match self.compare {
+            Compare::Equal => value == self.target,
+            Compare::NotEqual => value != self.target,
+            Compare::Less => value < self.target,
+            Compare::LessOrEqual => value <= self.target,
+            Compare::Greater => value > self.target,
+            Compare::GreaterOrEqual => value >= self.target
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct EnvConfig {
+    pub quirks: Quirks,
+    pub speed: usize, // Instructions per frame
+    // Reward is how much this value grew during the step, no reward when None
+    pub reward: Option<RamValue>,
+    // The episode ends when any of these holds, on a crash or at max_frames
+    pub done: Vec<Condition>,
+    pub max_frames: Option<u64>,
+    // Frames emulated per step with the same action
+    pub frame_skip: u32,
+    // Chance per frame of repeating the previous action instead of the new one
+    pub sticky_actions: f32
+}
+
+impl Default for EnvConfig {
+    fn default() -> EnvConfig {
+        EnvConfig {
+            quirks: Quirks::default(),
+            speed: 12,
+            reward: None,
+            done: Vec::new(),
+            max_frames: None,
+            frame_skip: 1,
+            sticky_actions: 0.0
+        }
+    }
+}
+
+pub struct Env {
+    rom: Vec<u8>,
+    config: EnvConfig,
+    cpu: CPU,
+    rng: ChaCha12Rng, // Sticky actions, apart from the machine's own generator
+    action: u16, // Last action applied to the keypad
+    score: i64,
+    done: bool
+}
+
+impl Env {
+    pub fn new(rom: &[u8], config: EnvConfig) -> Result<Env, String> {
+        if config.speed == 0 {
+            return Err(String::from("Speed must be at least 1 instruction per frame"));
+        }
+        if !(0.0..=1.0).contains(&config.sticky_actions) {
+            return Err(format!("Invalid sticky action chance {}, expected 0 to 1", config.sticky_actions));
+        }
+
+        let mut env = Env {
+            rom: rom.to_vec(),
+            config,
+            cpu: CPU::new(),
+            rng: ChaCha12Rng::seed_from_u64(0),
+            action: 0,
+            score: 0,
+            done: false
+        };
+        env.cpu = env.boot(0)?;
+
+        Ok(env)
+    }
+
+    fn boot(&self, seed: u64) -> Result<CPU, String> {
+        let mut cpu = CPU::new();
+        cpu.set_quirks(self.config.quirks);
+        cpu.set_speed(self.config.speed);
+        cpu.seed_rng(seed);
+        cpu.load_program(&self.rom).map_err(|e| e.to_string())?;
+        Ok(cpu)
+    }
+
+    // Start a new episode, the seed drives both Cxkk and sticky actions
+    pub fn reset(&mut self, seed: u64) -> Vec<u8> {
+        // The ROM fitted in new()
+        self.cpu = self.boot(seed).expect("ROM loaded before");
+        self.rng = ChaCha12Rng::seed_from_u64(seed);
+        self.action = 0;
+        self.score = self.config.reward.map_or(0, |value| value.read(self.cpu.ram()));
+        self.done = false;
+
+        self.observation()
+    }
+
+    // Hold the action for frame_skip frames
+    // Returns the screen, the reward earned and whether the episode ended
+    // Once done, further steps do nothing until reset
+    pub fn step(&mut self, action: u16) -> (Vec<u8>, f64, bool) {
+        if self.done {
+            return (self.observation(), 0.0, true);
+        }
+
+        for _ in 0..self.config.frame_skip.max(1) {
+            if self.config.sticky_actions == 0.0 || self.rng.random::<f32>() >= self.config.sticky_actions {
+                self.action = action;
+            }
+
+            let mut keypad = [false; 16];
+            for (key, down) in keypad.iter_mut().enumerate() {
+                *down = self.action >> key & 1 == 1;
+            }
+
+            if self.cpu.run_frame(keypad).is_err() || self.finished() {
+                self.done = true;
+                break;
+            }
+        }
+
+        let score = self.config.reward.map_or(0, |value| value.read(self.cpu.ram()));
+        let reward = (score - self.score) as f64;
+        self.score = score;
+
+        (self.observation(), reward, self.done)
+    }
+
+    fn finished(&self) -> bool {
+        let ram = self.cpu.ram();
+
+        self.config.max_frames.is_some_and(|limit| self.cpu.frames() >= limit)
+            || self.config.done.iter().any(|condition| condition.holds(ram))
+    }
+
+    // Screen as width * height bytes, 0 = off
+    pub fn observation(&self) -> Vec<u8> {
+        self.cpu.display().pixels().to_vec()
+    }
+
+    // Current value of the reward address
+    pub fn score(&self) -> i64 {
+        self.score
+    }
+
+    pub fn done(&self) -> bool {
+        self.done
+    }
+
+    // The machine, for RAM, the screen size or save states
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+}
@@ -0,0 +1,81 @@
+// Display filters to hide XOR flicker
+// Applied to the presented image only, the framebuffer is left untouched
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FilterMode {
+    None,
+    // Lit pixels fade out, keeping this fraction of their brightness per frame
+    Persistence(f32),
+    // Show pixels lit in either of the last two frames
+    Or
+}
+
+impl FilterMode {
+    pub fn parse(name: &str, decay: f32) -> Option<FilterMode> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Some(FilterMode::None),
+            "persistence" | "phosphor" => Some(FilterMode::Persistence(decay)),
+            "or" => Some(FilterMode::Or),
+            _ => None
+        }
+    }
+}
+
+pub struct Filter {
+    mode: FilterMode,
+    previous: Vec<u32>, // Last unfiltered frame
+    glow: Vec<[f32; 3]>, // Last presented colour per pixel
+    output: Vec<u32>
+}
+
+impl Filter {
+    pub fn new(mode: FilterMode) -> Filter {
+        Filter { mode, previous: Vec::new(), glow: Vec::new(), output: Vec::new() }
+    }
+
+    // Filtered copy of a frame, background is the palette's off colour
+    pub fn apply(&mut self, frame: &[u32], background: u32) -> &[u32] {
+        // Restart after a resize or at the first frame
+        if self.previous.len() != frame.len() {
+            self.previous = frame.to_vec();
+            self.glow = frame.iter().map(|&px| rgb(px)).collect();
+            self.output = frame.to_vec();
+        }
+
+        match self.mode {
+            FilterMode::None => self.output.copy_from_slice(frame),
+
+            FilterMode::Or => {
+                for (index, &px) in frame.iter().enumerate() {
+                    self.output[index] = if px == background { self.previous[index] } else { px };
+                }
+            }
+
+            FilterMode::Persistence(decay) => {
+                let bg = rgb(background);
+                for (index, &px) in frame.iter().enumerate() {
+                    // Lit pixels show at full colour, others fade towards the background
+                    if px != background {
+                        self.glow[index] = rgb(px);
+                    } else {
+                        let glow = &mut self.glow[index];
+                        for c in 0..3 {
+                            glow[c] = bg[c] + (glow[c] - bg[c]) * decay;
+                        }
+                    }
+                    self.output[index] = pack(self.glow[index]);
+                }
+            }
+        }
+
+        self.previous.copy_from_slice(frame);
+        &self.output
+    }
+}
+
+fn rgb(px: u32) -> [f32; 3] {
+    [((px >> 16) & 0xFF) as f32, ((px >> 8) & 0xFF) as f32, (px & 0xFF) as f32]
+}
+
+fn pack(c: [f32; 3]) -> u32 {
+    ((c[0].round() as u32) << 16) | ((c[1].round() as u32) << 8) | c[2].round() as u32
+}
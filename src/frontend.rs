@@ -0,0 +1,21 @@
+use crate::display::Display;
+
+// Where the machine gets its keys and shows its screen
+// Implemented by the minifb Window and the terminal
+pub trait Frontend {
+    // False once the user closed it or asked to quit
+    fn is_open(&self) -> bool;
+
+    // Emulator hotkeys (themes, screenshots...), once per frame
+    fn handle_hotkeys(&mut self);
+
+    // Chip8 keypad state
+    fn handle_key_events(&mut self) -> [bool; 16];
+
+    // Emulation stopped by the user, frames are still presented
+    fn paused(&self) -> bool;
+
+    // Present a frame, beeping tells whether the sound timer ran during it
+    // frames is the number of frames emulated so far
+    fn refresh(&mut self, display: &Display, beeping: bool, frames: u64);
+}
@@ -0,0 +1,182 @@
+use minifb::Key;
+
+use std::collections::BTreeMap;
+
+// Host key names accepted in the config file
+const KEY_NAMES: [(&str, Key); 106] = [
+    ("Key0", Key::Key0), ("Key1", Key::Key1), ("Key2", Key::Key2), ("Key3", Key::Key3),
+    ("Key4", Key::Key4), ("Key5", Key::Key5), ("Key6", Key::Key6), ("Key7", Key::Key7),
+    ("Key8", Key::Key8), ("Key9", Key::Key9),
+    ("A", Key::A), ("B", Key::B), ("C", Key::C), ("D", Key::D), ("E", Key::E),
+    ("F", Key::F), ("G", Key::G), ("H", Key::H), ("I", Key::I), ("J", Key::J),
+    ("K", Key::K), ("L", Key::L), ("M", Key::M), ("N", Key::N), ("O", Key::O),
+    ("P", Key::P), ("Q", Key::Q), ("R", Key::R), ("S", Key::S), ("T", Key::T),
+    ("U", Key::U), ("V", Key::V), ("W", Key::W), ("X", Key::X), ("Y", Key::Y),
+    ("Z", Key::Z),
+    ("F1", Key::F1), ("F2", Key::F2), ("F3", Key::F3), ("F4", Key::F4), ("F5", Key::F5),
+    ("F6", Key::F6), ("F7", Key::F7), ("F8", Key::F8), ("F9", Key::F9), ("F10", Key::F10),
+    ("F11", Key::F11), ("F12", Key::F12), ("F13", Key::F13), ("F14", Key::F14), ("F15", Key::F15),
+    ("Down", Key::Down), ("Left", Key::Left), ("Right", Key::Right), ("Up", Key::Up),
+    ("Apostrophe", Key::Apostrophe), ("Backquote", Key::Backquote), ("Backslash", Key::Backslash),
+    ("Comma", Key::Comma), ("Equal", Key::Equal), ("LeftBracket", Key::LeftBracket),
+    ("Minus", Key::Minus), ("Period", Key::Period), ("RightBracket", Key::RightBracket),
+    ("Semicolon", Key::Semicolon), ("Slash", Key::Slash), ("Backspace", Key::Backspace),
+    ("Delete", Key::Delete), ("End", Key::End), ("Enter", Key::Enter), ("Escape", Key::Escape),
+    ("Home", Key::Home), ("Insert", Key::Insert), ("Menu", Key::Menu),
+    ("PageDown", Key::PageDown), ("PageUp", Key::PageUp), ("Pause", Key::Pause),
+    ("Space", Key::Space), ("Tab", Key::Tab), ("NumLock", Key::NumLock),
+    ("CapsLock", Key::CapsLock), ("ScrollLock", Key::ScrollLock),
+    ("LeftShift", Key::LeftShift), ("RightShift", Key::RightShift),
+    ("LeftCtrl", Key::LeftCtrl), ("RightCtrl", Key::RightCtrl),
+    ("NumPad0", Key::NumPad0), ("NumPad1", Key::NumPad1), ("NumPad2", Key::NumPad2),
+    ("NumPad3", Key::NumPad3), ("NumPad4", Key::NumPad4), ("NumPad5", Key::NumPad5),
+    ("NumPad6", Key::NumPad6), ("NumPad7", Key::NumPad7), ("NumPad8", Key::NumPad8),
+    ("NumPad9", Key::NumPad9), ("NumPadDot", Key::NumPadDot), ("NumPadSlash", Key::NumPadSlash),
+    ("NumPadAsterisk", Key::NumPadAsterisk), ("NumPadMinus", Key::NumPadMinus),
+    ("NumPadPlus", Key::NumPadPlus), ("NumPadEnter", Key::NumPadEnter),
+    ("LeftAlt", Key::LeftAlt), ("RightAlt", Key::RightAlt),
+    ("LeftSuper", Key::LeftSuper), ("RightSuper", Key::RightSuper),
+];
+
+// Host keys bound to each of the 16 chip8 keys
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    bindings: [Vec<Key>; 16]
+}
+
+impl Default for Keymap {
+    // 1 2 3 C    1 2 3 4
+    // 4 5 6 D    Q W E R
+    // 7 8 9 E    A S D F
+    // A 0 B F    Z X C V
+    fn default() -> Keymap {
+        Keymap { bindings: [
+            vec![Key::X],    // 0
+            vec![Key::Key1], // 1
+            vec![Key::Key2], // 2
+            vec![Key::Key3], // 3
+            vec![Key::Q],    // 4
+            vec![Key::W],    // 5
+            vec![Key::E],    // 6
+            vec![Key::A],    // 7
+            vec![Key::S],    // 8
+            vec![Key::D],    // 9
+            vec![Key::Z],    // A
+            vec![Key::C],    // B
+            vec![Key::Key4], // C
+            vec![Key::R],    // D
+            vec![Key::F],    // E
+            vec![Key::V],    // F
+        ]}
+    }
+}
+
+impl Keymap {
+    // Replace the bindings of the chip8 keys listed in a config table
+    // Table keys are hex digits ("0" - "F"), values are host key names
+    pub fn apply(&mut self, table: &BTreeMap<String, Vec<String>>) -> Result<(), String> {
+        for (index, keys) in parse_table(table)? {
+            self.bindings[index] = keys;
+        }
+
+        Ok(())
+    }
+
+    // Bind more host keys to the chip8 keys listed in a table, keeping the
+    // current bindings
+    // Host keys already bound to any chip8 key are left where they are
+    pub fn add(&mut self, table: &BTreeMap<String, Vec<String>>) -> Result<(), String> {
+        for (index, keys) in parse_table(table)? {
+            for key in keys {
+                if !self.bindings.iter().any(|bound| bound.contains(&key)) {
+                    self.bindings[index].push(key);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Chip8 keypad state from the host keys currently held
+    pub fn keypad(&self, pressed: &[Key]) -> [bool; 16] {
+        let mut keys = [false; 16];
+
+        for (index, bound) in self.bindings.iter().enumerate() {
+            keys[index] = bound.iter().any(|k| pressed.contains(k));
+        }
+
+        keys
+    }
+}
+
+fn parse_table(table: &BTreeMap<String, Vec<String>>) -> Result<Vec<(usize, Vec<Key>)>, String> {
+    let mut parsed = Vec::new();
+
+    for (chip8_key, names) in table {
+        let index = match u8::from_str_radix(chip8_key, 16) {
+            Ok(v) if v < 16 => v as usize,
+            _ => {return Err(format!("Invalid chip8 key '{}' in keymap", chip8_key));}
+        };
+
+        let mut keys = Vec::new();
+        for name in names {
+            match parse_key(name) {
+                Some(k) => keys.push(k),
+                None => {return Err(format!("Unknown key '{}' in keymap", name));}
+            }
+        }
+
+        parsed.push((index, keys));
+    }
+
+    Ok(parsed)
+}
+
+// Config name of a host key
+pub fn key_name(key: Key) -> &'static str {
+    KEY_NAMES.iter()
+        .find(|&&(_, k)| k == key)
+        .map_or("Unknown", |&(n, _)| n)
+}
+
+// Look up a host key by name, ignoring case
+pub fn parse_key(name: &str) -> Option<Key> {
+    KEY_NAMES.iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, k)| k)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(entries: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+        entries.iter().map(|(k, names)| (k.to_string(), names.iter().map(|n| n.to_string()).collect())).collect()
+    }
+
+    #[test]
+    fn add_keeps_bindings() {
+        let mut keymap = Keymap::default();
+        keymap.apply(&table(&[("5", &["I"])])).unwrap();
+        keymap.add(&table(&[("5", &["Up"]), ("8", &["Down"])])).unwrap();
+
+        assert_eq!(keymap.bindings[5], vec![Key::I, Key::Up]);
+        assert_eq!(keymap.bindings[8], vec![Key::S, Key::Down]);
+    }
+
+    #[test]
+    fn add_skips_bound_keys() {
+        let mut keymap = Keymap::default();
+        keymap.apply(&table(&[("2", &["Up"])])).unwrap();
+        keymap.add(&table(&[("5", &["Up"])])).unwrap();
+
+        assert_eq!(keymap.bindings[2], vec![Key::Up]);
+        assert_eq!(keymap.bindings[5], vec![Key::W]);
+    }
+
+    #[test]
+    fn invalid_table() {
+        assert!(Keymap::default().apply(&table(&[("G", &["Up"])])).is_err());
+        assert!(Keymap::default().add(&table(&[("1", &["Nope"])])).is_err());
+    }
+}
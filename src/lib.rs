@@ -0,0 +1,52 @@
+// Emulator core, shared by the desktop binary and the wasm build
+pub mod cpu;
+pub mod disasm;
+pub mod display;
+pub mod env;
+pub mod frontend;
+pub mod movie;
+pub mod palette;
+pub mod profile;
+pub mod quirks;
+pub mod synth;
+pub mod trace;
+
+// Desktop frontends, audio and file formats
+#[cfg(feature = "native")]
+pub mod audio;
+#[cfg(feature = "native")]
+pub mod batch;
+#[cfg(feature = "native")]
+pub mod cli;
+#[cfg(feature = "native")]
+pub mod compare;
+#[cfg(feature = "native")]
+pub mod config;
+#[cfg(feature = "native")]
+pub mod database;
+#[cfg(feature = "native")]
+pub mod filter;
+#[cfg(feature = "native")]
+pub mod keymap;
+#[cfg(feature = "native")]
+pub mod overlay;
+#[cfg(feature = "native")]
+pub mod recorder;
+#[cfg(feature = "native")]
+pub mod screenshot;
+#[cfg(feature = "native")]
+pub mod terminal;
+#[cfg(feature = "native")]
+pub mod window;
+
+// libretro core, see --features libretro
+#[cfg(feature = "libretro")]
+mod libretro;
+
+// Python bindings, see --features python
+#[cfg(feature = "python")]
+mod python;
+
+// Browser API, see web/
+#[cfg(target_arch = "wasm32")]
+mod wasm;
@@ -0,0 +1,420 @@
+// libretro core, for RetroArch and other frontends
+// Built with --features libretro, the frontend loads the cdylib and calls
+// the retro_* functions below from one thread
+//
+// Video is XRGB8888 at the display resolution, audio is the beeper from
+// the synth at 44100Hz, input is the RetroPad and the keyboard
+
+use std::ffi::{c_char, c_void, CStr};
+use std::sync::Mutex;
+
+use crate::cpu::CPU;
+use crate::display::{WIDTH, HEIGHT};
+use crate::palette::Palette;
+use crate::quirks::Quirks;
+use crate::synth::{Synth, Tone, FPS, SAMPLE_RATE};
+
+const RETRO_API_VERSION: u32 = 1;
+
+const RETRO_ENVIRONMENT_GET_VARIABLE: u32 = 15;
+const RETRO_ENVIRONMENT_SET_VARIABLES: u32 = 16;
+const RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE: u32 = 17;
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: u32 = 10;
+const RETRO_PIXEL_FORMAT_XRGB8888: u32 = 1;
+
+const RETRO_DEVICE_JOYPAD: u32 = 1;
+const RETRO_DEVICE_KEYBOARD: u32 = 3;
+
+const RETRO_MEMORY_SYSTEM_RAM: u32 = 2;
+const RETRO_REGION_NTSC: u32 = 0;
+
+// RetroPad button to chip8 key: the d-pad is 2/8/4/6 and B is 5 like most
+// games, the other buttons cover the remaining common keys
+// Up, Down, Left, Right, B, A, Y, X, L, R, Select, Start
+const JOYPAD: [(u32, usize); 12] = [
+    (4, 0x2), (5, 0x8), (6, 0x4), (7, 0x6),
+    (0, 0x5), (8, 0x0), (1, 0x1), (9, 0x3),
+    (10, 0x7), (11, 0x9), (2, 0xA), (3, 0xF)
+];
+
+// RETROK codes of the QWERTY layout, in chip8 key order 0-F
+const KEYBOARD: [u32; 16] = [
+    b'x' as u32, b'1' as u32, b'2' as u32, b'3' as u32,
+    b'q' as u32, b'w' as u32, b'e' as u32, b'a' as u32,
+    b's' as u32, b'd' as u32, b'z' as u32, b'c' as u32,
+    b'4' as u32, b'r' as u32, b'f' as u32, b'v' as u32
+];
+
+// Core options, shown in the frontend menu
+const VARIABLES: [(&CStr, &CStr); 3] = [
+    (c"chip8_quirks", c"Quirks; default|vip|schip|xochip"),
+    (c"chip8_speed", c"Instructions per frame; 12|8|10|15|20|30|50|100|200|500|1000"),
+    (c"chip8_theme", c"Theme; mono|green|amber|lcd|octo")
+];
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    base_width: u32,
+    base_height: u32,
+    max_width: u32,
+    max_height: u32,
+    aspect_ratio: f32
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    fps: f64,
+    sample_rate: f64
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    geometry: RetroGameGeometry,
+    timing: RetroSystemTiming
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char
+}
+
+#[repr(C)]
+struct RetroVariable {
+    key: *const c_char,
+    value: *const c_char
+}
+
+type EnvironmentFn = unsafe extern "C" fn(cmd: u32, data: *mut c_void) -> bool;
+type VideoRefreshFn = unsafe extern "C" fn(data: *const c_void, width: u32, height: u32, pitch: usize);
+type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = unsafe extern "C" fn();
+type InputStateFn = unsafe extern "C" fn(port: u32, device: u32, index: u32, id: u32) -> i16;
+
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>
+}
+
+// The loaded game
+struct Core {
+    rom: Vec<u8>,
+    cpu: CPU,
+    quirks: Quirks,
+    speed: usize,
+    palette: Palette,
+    synth: Synth,
+    video: Vec<u32>,
+    audio: Vec<i16>
+}
+
+impl Core {
+    // Fresh machine with the ROM, for loading and resetting
+    fn boot(&mut self) {
+        let mut cpu = CPU::new();
+        cpu.set_quirks(self.quirks);
+        cpu.set_speed(self.speed);
+        // The ROM fitted when the game was loaded
+        let _ = cpu.load_program(&self.rom);
+        self.cpu = cpu;
+        self.synth = Synth::new(Tone::default());
+    }
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None
+});
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn callbacks() -> Callbacks {
+    *CALLBACKS.lock().unwrap()
+}
+
+fn environment(cmd: u32, data: *mut c_void) -> bool {
+    match callbacks().environment {
+        Some(callback) => unsafe { callback(cmd, data) },
+        None => false
+    }
+}
+
+// Current value of a core option
+fn variable(key: &CStr) -> Option<String> {
+    let mut var = RetroVariable { key: key.as_ptr(), value: std::ptr::null() };
+
+    if !environment(RETRO_ENVIRONMENT_GET_VARIABLE, &mut var as *mut _ as *mut c_void) || var.value.is_null() {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr(var.value) }.to_string_lossy().into_owned())
+}
+
+// Apply the core options, quirks and speed take effect on the running game
+fn apply_variables(core: &mut Core) {
+    if let Some(quirks) = variable(c"chip8_quirks").and_then(|name| Quirks::preset(&name)) {
+        core.quirks = quirks;
+        core.cpu.set_quirks(quirks);
+    }
+    if let Some(speed) = variable(c"chip8_speed").and_then(|speed| speed.parse().ok()) {
+        core.speed = speed;
+        core.cpu.set_speed(speed);
+    }
+    if let Some(palette) = variable(c"chip8_theme").and_then(|name| Palette::theme(&name)) {
+        core.palette = palette;
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_api_version() -> u32 {
+    RETRO_API_VERSION
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    CALLBACKS.lock().unwrap().environment = Some(callback);
+
+    let mut variables: Vec<RetroVariable> = VARIABLES.iter()
+        .map(|(key, value)| RetroVariable { key: key.as_ptr(), value: value.as_ptr() })
+        .collect();
+    variables.push(RetroVariable { key: std::ptr::null(), value: std::ptr::null() });
+    environment(RETRO_ENVIRONMENT_SET_VARIABLES, variables.as_mut_ptr() as *mut c_void);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    CALLBACKS.lock().unwrap().video_refresh = Some(callback);
+}
+
+// Only the batch callback is used
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_audio_sample(_callback: AudioSampleFn) {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    CALLBACKS.lock().unwrap().audio_sample_batch = Some(callback);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    CALLBACKS.lock().unwrap().input_poll = Some(callback);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    CALLBACKS.lock().unwrap().input_state = Some(callback);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_init() {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_deinit() {
+    *CORE.lock().unwrap() = None;
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    let info = unsafe { &mut *info };
+    info.library_name = c"chip8-rust".as_ptr();
+    info.library_version = c"0.1.0".as_ptr();
+    info.valid_extensions = c"ch8|c8|rom".as_ptr();
+    info.need_fullpath = false;
+    info.block_extract = false;
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    let info = unsafe { &mut *info };
+    info.geometry = RetroGameGeometry {
+        base_width: WIDTH as u32,
+        base_height: HEIGHT as u32,
+        max_width: WIDTH as u32 * 2, // Room for SCHIP hires
+        max_height: HEIGHT as u32 * 2,
+        aspect_ratio: 2.0
+    };
+    info.timing = RetroSystemTiming { fps: FPS as f64, sample_rate: SAMPLE_RATE as f64 };
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_controller_port_device(_port: u32, _device: u32) {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = CORE.lock().unwrap().as_mut() {
+        core.boot();
+    }
+}
+
+// Input, one emulated frame, then video and audio
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    let mut core = CORE.lock().unwrap();
+    let core = match core.as_mut() {
+        Some(c) => c,
+        None => {return;}
+    };
+
+    let mut updated = false;
+    if environment(RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut _ as *mut c_void) && updated {
+        apply_variables(core);
+    }
+
+    let mut keypad = [false; 16];
+    if let (Some(poll), Some(state)) = (callbacks.input_poll, callbacks.input_state) {
+        unsafe { poll() };
+
+        for (button, key) in JOYPAD {
+            keypad[key] |= unsafe { state(0, RETRO_DEVICE_JOYPAD, 0, button) } != 0;
+        }
+        for (key, code) in KEYBOARD.iter().enumerate() {
+            keypad[key] |= unsafe { state(0, RETRO_DEVICE_KEYBOARD, 0, *code) } != 0;
+        }
+    }
+
+    // A crashed program freezes on its last frame
+    let frame = core.cpu.frames();
+    let beeping = core.cpu.run_frame(keypad).unwrap_or(false);
+
+    let display = core.cpu.display();
+    core.video.clear();
+    core.video.extend(display.pixels().iter().map(|&planes| core.palette.color(planes as usize)));
+
+    if let Some(video_refresh) = callbacks.video_refresh {
+        let (width, height) = (display.width(), display.height());
+        unsafe { video_refresh(core.video.as_ptr() as *const c_void, width as u32, height as u32, width * 4) };
+    }
+
+    core.audio.clear();
+    for _ in 0..Synth::frame_samples(frame) {
+        let level = (core.synth.sample(beeping) * i16::MAX as f32) as i16;
+        core.audio.extend([level, level]);
+    }
+
+    if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+        unsafe { audio_sample_batch(core.audio.as_ptr(), core.audio.len() / 2) };
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_serialize_size() -> usize {
+    CORE.lock().unwrap().as_ref().map_or(0, |core| core.cpu.save_state().len())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let core = CORE.lock().unwrap();
+    let state = match core.as_ref() {
+        Some(core) => core.cpu.save_state(),
+        None => {return false;}
+    };
+
+    if size < state.len() {
+        return false;
+    }
+    unsafe { std::ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len()) };
+    true
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let state = unsafe { std::slice::from_raw_parts(data as *const u8, size) };
+
+    match CORE.lock().unwrap().as_mut() {
+        Some(core) => core.cpu.load_state(state).is_ok(),
+        None => false
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_cheat_set(_index: u32, _enabled: bool, _code: *const c_char) {}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() {
+        return false;
+    }
+    let game = unsafe { &*game };
+    if game.data.is_null() {
+        return false;
+    }
+    let rom = unsafe { std::slice::from_raw_parts(game.data as *const u8, game.size) }.to_vec();
+
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut _ as *mut c_void) {
+        return false;
+    }
+
+    let mut core = Core {
+        rom,
+        cpu: CPU::new(),
+        quirks: Quirks::default(),
+        speed: 12,
+        palette: Palette::default(),
+        synth: Synth::new(Tone::default()),
+        video: Vec::new(),
+        audio: Vec::new()
+    };
+    if core.cpu.load_program(&core.rom).is_err() {
+        return false;
+    }
+    apply_variables(&mut core);
+    core.boot();
+
+    *CORE.lock().unwrap() = Some(core);
+    true
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_load_game_special(_game_type: u32, _info: *const RetroGameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_unload_game() {
+    *CORE.lock().unwrap() = None;
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_region() -> u32 {
+    RETRO_REGION_NTSC
+}
+
+// System RAM, for cheats and achievements
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_memory_data(id: u32) -> *mut c_void {
+    match (id, CORE.lock().unwrap().as_mut()) {
+        (RETRO_MEMORY_SYSTEM_RAM, Some(core)) => core.cpu.ram_mut().as_mut_ptr() as *mut c_void,
+        _ => std::ptr::null_mut()
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_memory_size(id: u32) -> usize {
+    match (id, CORE.lock().unwrap().as_ref()) {
+        (RETRO_MEMORY_SYSTEM_RAM, Some(core)) => core.cpu.ram().len(),
+        _ => 0
+    }
+}
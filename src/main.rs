@@ -1,47 +1,238 @@
-extern crate minifb;
-extern crate rand;
-extern crate rodio;
+use chip8::audio::Audio;
+use chip8::batch;
+use chip8::cli;
+use chip8::compare;
+use chip8::config::Config;
+use chip8::cpu::CPU;
+use chip8::database::Database;
+use chip8::frontend::Frontend;
+use chip8::profile::Profiler;
+use chip8::terminal::Terminal;
+use chip8::trace::Tracer;
+use chip8::window::Window;
 
-mod audio;
-use audio::Audio;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 
-mod window;
-use window::Window;
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
 
-mod cpu;
-use cpu::CPU;
+    // Headless jobs, stdout is left to the JSON summary
+    // Exits with 1 when the batch or any job could not start
+    if args.get(1).is_some_and(|a| a == "batch") {
+        let batch_args = match cli::parse_batch(&args[2..]) {
+            Ok(Some(a)) => a,
+            Ok(None) => {return println!("{}", cli::usage(&args[0]));}
+            Err(err) => {
+                eprintln!("{}\n\n{}", err, cli::usage(&args[0]));
+                std::process::exit(1);
+            }
+        };
+        match batch::run(batch_args) {
+            Ok(0) => (),
+            Ok(failed) => {
+                eprintln!("{} jobs could not start", failed);
+                std::process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("Batch failed: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-fn main() {
-    println!("CHIP-8 emulator in Rust!");
+    let cli_args = match cli::parse(&args[1..]) {
+        Ok(Some(a)) => a,
+        Ok(None) => {return println!("{}", cli::usage(&args[0]));}
+        Err(err) => {return eprintln!("{}\n\n{}", err, cli::usage(&args[0]));}
+    };
 
-    let args: Vec<String> = std::env::args().collect();
+    // Scripted runs keep stdout for their results
+    let interactive = !cli_args.headless && cli_args.compare.is_none();
+    if interactive {
+        println!("CHIP-8 emulator in Rust!");
+    }
 
-    if args.len() != 2 {
-        return eprintln!("Usage: {} <rom-file-name>", args[0]);
+    let config = match &cli_args.config {
+        Some(path) => Config::load_from(path),
+        None => Config::load()
+    };
+    let config = match config {
+        Ok(c) => c,
+        Err(err) => {return eprint!("Could not load config: {}", err);}
+    };
+
+    let rom = match std::fs::read(&cli_args.rom) {
+        Ok(r) => r,
+        Err(err) => {return eprint!("Could not load ROM: {}", err);}
+    };
+
+    // Known ROMs get their platform quirks, speed and colours
+    let rom_info = match Database::load() {
+        Ok(db) => db.lookup(&rom),
+        Err(err) => {
+            eprintln!("Could not load ROM database: {}", err);
+            None
+        }
+    };
+
+    if let Some(info) = &rom_info && interactive {
+        println!("Found in database: {} by {} ({})", info.title, info.authors.join(", "), info.platform);
+    }
+
+    let mut options = match config.options(cli_args, rom_info.as_ref().map(|i| &i.settings)) {
+        Ok(o) => o,
+        Err(err) => {return eprint!("Invalid settings: {}", err);}
+    };
+
+    // Database controls go on top of the user's bindings
+    if let Some(info) = &rom_info && let Err(err) = options.keymap.add(&info.keys) {
+        return eprint!("Invalid settings: {}", err);
     }
 
-    let filename = String::from(&args[1]);
+    if options.debug {
+        println!("{:#?}", options);
+    }
 
-    let audio = match Audio::new() {
-        Ok(a) => a,
-        Err(err) => {return eprint!("Could not initialize audio device: {}", err);}
+    let filename = options.rom.display().to_string();
+
+    let trace_output: Option<Box<dyn Write + Send + Sync>> = match &options.trace {
+        Some(path) => match File::create(path) {
+            Ok(f) => Some(Box::new(BufWriter::new(f))),
+            Err(err) => {return eprint!("Could not create trace file: {}", err);}
+        },
+        None => None
     };
 
-    let win = match Window::new(&format!("chip8-rust: {}", filename)) {
-        Ok(w) => w,
-        Err(err) => {return eprint!("Could not initialize window: {}", err);}
+    // Check against another emulator's trace, without window or audio
+    // Exits with 1 on a divergence, a fault or when it could not run
+    if let Some(reference) = &options.compare {
+        let mut cpu = CPU::new();
+        cpu.set_quirks(options.quirks);
+        cpu.set_speed(options.speed);
+        cpu.seed_rng(options.seed.unwrap_or(0));
+        cpu.set_tracer(Some(Tracer::new(trace_output, options.trace_range)));
+        if let Err(err) = cpu.load_program(&rom) {
+            return eprint!("Could not load ROM: {}", err);
+        }
+
+        let result = compare::run(&mut cpu, reference, options.movie.as_deref());
+        if let Some(tracer) = cpu.tracer_mut()
+            && let Err(err) = tracer.flush() {
+            eprintln!("{}", err);
+        }
+
+        match result {
+            Ok((true, report)) => println!("{}", report),
+            Ok((false, report)) => {
+                println!("{}", report);
+                std::process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("Could not compare: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut audio = if options.mute {
+        Audio::muted()
+    } else {
+        match Audio::new(options.tone) {
+            Ok(a) => a,
+            Err(err) => {
+                eprintln!("Could not initialize audio device, continuing without sound: {}", err);
+                Audio::muted()
+            }
+        }
     };
 
-    let mut cpu = CPU::new(win, audio);
+    if let Some(path) = &options.wav
+        && let Err(err) = audio.start_wav(path, options.tone) {
+        return eprint!("Could not start WAV export: {}", err);
+    }
 
-    println!("Loading ROM: {}", filename);
-    match cpu.load_rom(filename) {
-        Ok(()) => (),
-        Err(err) => {return eprint!("Could not load ROM: {}", err.to_string());}
+    let mut win: Box<dyn Frontend> = if let Some(mode) = options.terminal {
+        let mut term = match Terminal::new(mode) {
+            Ok(t) => t,
+            Err(err) => {return eprint!("Could not initialize terminal: {}", err);}
+        };
+        term.set_palette(options.palette.clone());
+        term.set_keymap(options.keymap.clone());
+        Box::new(term)
+    } else {
+        let mut win = if options.headless {
+            Window::headless()
+        } else {
+            match Window::new(&format!("chip8-rust: {}", filename), options.scale, options.fullscreen, options.scaling) {
+                Ok(w) => w,
+                Err(err) => {return eprint!("Could not initialize window: {}", err);}
+            }
+        };
+
+        win.set_palette(options.palette.clone());
+        win.set_filter(options.filter);
+        win.set_screenshot_dir(options.screenshot_dir.clone());
+        win.set_record_format(&options.record_format);
+        win.set_tone(options.tone);
+        if let Some(path) = &options.record {
+            win.start_recording(path);
+        }
+        win.set_keymap(options.keymap.clone());
+        if options.debug {
+            win.read_commands();
+        }
+        Box::new(win)
     };
 
-    match cpu.run_loop() {
+    let mut cpu = CPU::new();
+    cpu.set_quirks(options.quirks);
+    cpu.set_speed(options.speed);
+    cpu.set_frame_limit(options.frames);
+    cpu.seed_rng(options.seed.unwrap_or_else(rand::random));
+
+    // Tracing copies the registers twice per instruction, so it only runs
+    // for --trace, or with --debug to dump the recent instructions on a crash
+    if options.trace.is_some() || options.debug {
+        cpu.set_tracer(Some(Tracer::new(trace_output, options.trace_range)));
+    }
+    if options.profile.is_some() {
+        cpu.set_profiler(Some(Profiler::new(rom.len())));
+    }
+
+    if interactive {
+        println!("Loading ROM: {}", filename);
+    }
+    match cpu.load_program(&rom) {
         Ok(()) => (),
-        Err(err) => {return eprint!("CPU crashed: {}", err);}
+        Err(err) => {return eprint!("Could not load ROM: {}", err);}
     };
+
+    let result = cpu.run_loop(win.as_mut(), &mut audio);
+    // Leave the terminal's raw mode and alternate screen before reporting
+    drop(win);
+
+    if let Some(tracer) = cpu.tracer_mut()
+        && let Err(err) = tracer.flush() {
+        eprintln!("{}", err);
+    }
+
+    if options.debug {
+        println!("{}", cpu.dump_state());
+    }
+
+    if let (Some(path), Some(profiler)) = (&options.profile, cpu.profiler())
+        && let Err(err) = std::fs::write(path, profiler.report(cpu.ram())) {
+        eprintln!("Could not write profile: {}", err);
+    }
+
+    if let Err(err) = result {
+        match cpu.fault_dump(err) {
+            Some(dump) => eprintln!("{}", dump),
+            None => eprintln!("{} at {:#05X}, --debug shows the instructions before it", err, cpu.pc())
+        }
+        eprint!("CPU crashed: {}", err);
+    }
 }
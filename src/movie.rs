@@ -0,0 +1,130 @@
+// Input movie: the keys held on each frame, for replaying a run headless
+//
+//     # comment
+//     - 120      nothing held for 120 frames
+//     5 30       key 5 for 30 frames
+//     46 2       keys 4 and 6 together for 2 frames
+//     A          key A for 1 frame
+//
+// Keys are hex digits, '-' is no key, the count defaults to 1
+
+use std::path::Path;
+
+#[derive(Clone, Default, Debug)]
+pub struct Movie {
+    // One entry per line: frame count up to the end of the line, keypad
+    // bitmask (bit n = key n), so long holds take no memory
+    runs: Vec<(u64, u16)>
+}
+
+impl Movie {
+    pub fn load(path: &Path) -> Result<Movie, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Movie::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut runs = Vec::new();
+        let mut frames: u64 = 0;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let keys = fields.next().unwrap_or("-");
+            let count = match fields.next() {
+                Some(count) => match count.parse::<u64>() {
+                    Ok(c) => c,
+                    Err(_) => {return Err(format!("line {}: invalid frame count '{}'", number + 1, count));}
+                },
+                None => 1
+            };
+            if fields.next().is_some() {
+                return Err(format!("line {}: expected keys and a frame count", number + 1));
+            }
+
+            let mut mask = 0u16;
+            if keys != "-" {
+                for key in keys.chars() {
+                    match key.to_digit(16) {
+                        Some(k) => mask |= 1 << k,
+                        None => {return Err(format!("line {}: invalid key '{}'", number + 1, key));}
+                    }
+                }
+            }
+
+            frames = match frames.checked_add(count) {
+                Some(f) => f,
+                None => {return Err(format!("line {}: too many frames", number + 1));}
+            };
+            if count > 0 {
+                runs.push((frames, mask));
+            }
+        }
+
+        Ok(Movie { runs })
+    }
+
+    // Frames in the movie
+    pub fn len(&self) -> u64 {
+        self.runs.last().map_or(0, |&(end, _)| end)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    // Keys held on a frame, none past the end
+    pub fn keypad(&self, frame: u64) -> [bool; 16] {
+        let run = self.runs.partition_point(|&(end, _)| end <= frame);
+        let mask = self.runs.get(run).map_or(0, |&(_, mask)| mask);
+
+        let mut keypad = [false; 16];
+        for (key, down) in keypad.iter_mut().enumerate() {
+            *down = mask >> key & 1 == 1;
+        }
+        keypad
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held(keypad: [bool; 16]) -> Vec<usize> {
+        (0..16).filter(|&key| keypad[key]).collect()
+    }
+
+    #[test]
+    fn parse_lines() {
+        let movie = Movie::parse("# intro\n- 3\n5 2   # hold 5\n\n4a\nF 0\n").unwrap();
+        assert_eq!(movie.len(), 6);
+
+        assert_eq!(movie.keypad(0), [false; 16]);
+        assert_eq!(held(movie.keypad(3)), vec![5]);
+        assert_eq!(held(movie.keypad(5)), vec![4, 0xA]);
+        assert_eq!(movie.keypad(6), [false; 16]);
+        assert_eq!(movie.keypad(u64::MAX), [false; 16]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        for text in ["5 x", "5 -1", "5 2 3", "G", "5\n4 2 1", "5 18446744073709551615\n4 1", "5 99999999999999999999"] {
+            assert!(Movie::parse(text).is_err(), "{}", text);
+        }
+        assert!(Movie::parse("5\n- x").unwrap_err().starts_with("line 2:"));
+        assert!(Movie::parse("# nothing\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn long_holds() {
+        let movie = Movie::parse("5 99999999999\n- 0\n4").unwrap();
+        assert_eq!(movie.len(), 100_000_000_000);
+        assert_eq!(held(movie.keypad(99_999_999_998)), vec![5]);
+        assert_eq!(held(movie.keypad(99_999_999_999)), vec![4]);
+        assert_eq!(movie.keypad(100_000_000_000), [false; 16]);
+    }
+}
@@ -0,0 +1,168 @@
+use std::time::{Duration, Instant};
+
+// How long status messages stay on screen
+const MESSAGE_TIME: Duration = Duration::from_secs(2);
+
+// Frame rate the emulated speed is measured against
+const TARGET_FPS: u32 = 60;
+
+// 3x5 font, one row of 3 bits per line from the top
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+const FONT: [(char, u16); 43] = [
+    ('0', 0b111_101_101_101_111), ('1', 0b010_110_010_010_111), ('2', 0b111_001_111_100_111),
+    ('3', 0b111_001_111_001_111), ('4', 0b101_101_111_001_001), ('5', 0b111_100_111_001_111),
+    ('6', 0b111_100_111_101_111), ('7', 0b111_001_001_010_010), ('8', 0b111_101_111_101_111),
+    ('9', 0b111_101_111_001_111), ('A', 0b010_101_111_101_101), ('B', 0b110_101_110_101_110),
+    ('C', 0b011_100_100_100_011), ('D', 0b110_101_101_101_110), ('E', 0b111_100_110_100_111),
+    ('F', 0b111_100_110_100_100), ('G', 0b011_100_101_101_011), ('H', 0b101_101_111_101_101),
+    ('I', 0b111_010_010_010_111), ('J', 0b001_001_001_101_010), ('K', 0b101_101_110_101_101),
+    ('L', 0b100_100_100_100_111), ('M', 0b101_111_111_101_101), ('N', 0b110_101_101_101_101),
+    ('O', 0b010_101_101_101_010), ('P', 0b110_101_110_100_100), ('Q', 0b010_101_101_110_011),
+    ('R', 0b110_101_110_101_101), ('S', 0b011_100_010_001_110), ('T', 0b111_010_010_010_010),
+    ('U', 0b101_101_101_101_111), ('V', 0b101_101_101_101_010), ('W', 0b101_101_111_111_101),
+    ('X', 0b101_101_010_101_101), ('Y', 0b101_101_010_010_010), ('Z', 0b111_001_010_100_111),
+    ('.', 0b000_000_000_000_010), (':', 0b000_010_000_010_000), ('-', 0b000_000_111_000_000),
+    ('%', 0b101_001_010_100_101), ('/', 0b001_001_010_100_100), (' ', 0),
+    ('?', 0b111_001_010_000_010)
+];
+
+// Text drawn over the presented image, never recorded or saved in screenshots
+// FPS, emulated speed and pressed keys when visible, status messages and
+// the pause indicator always
+pub struct Overlay {
+    visible: bool,
+    frames: u32, // Frames presented since second_start
+    second_start: Instant,
+    emulated_start: u64, // Emulated frame count at second_start
+    fps: u32,
+    speed: u32, // Emulated frames per second, as a percentage of TARGET_FPS
+    message: Option<(String, Instant)>
+}
+
+impl Default for Overlay {
+    fn default() -> Overlay {
+        Overlay::new()
+    }
+}
+
+impl Overlay {
+    pub fn new() -> Overlay {
+        Overlay {
+            visible: false,
+            frames: 0,
+            second_start: Instant::now(),
+            emulated_start: 0,
+            fps: 0,
+            speed: 0,
+            message: None
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    // Show a status message for a moment
+    pub fn message(&mut self, text: &str) {
+        self.message = Some((String::from(text), Instant::now()));
+    }
+
+    // Count a presented frame, emulated is the machine's frame count
+    // The speed drops to 0% while paused and below 100% when the
+    // emulation cannot keep up
+    pub fn tick(&mut self, emulated: u64) {
+        self.frames += 1;
+
+        let elapsed = self.second_start.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            let seconds = elapsed.as_secs_f64();
+            self.fps = (self.frames as f64 / seconds).round() as u32;
+            let emulated_fps = emulated.saturating_sub(self.emulated_start) as f64 / seconds;
+            self.speed = (emulated_fps * 100.0 / TARGET_FPS as f64).round() as u32;
+            self.frames = 0;
+            self.second_start = Instant::now();
+            self.emulated_start = emulated;
+        }
+    }
+
+    // Compose onto a window sized buffer
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(&mut self, dst: &mut [u32], width: usize, height: usize,
+                keypad: &[bool; 16], paused: bool, color: u32, background: u32) {
+
+        let scale = (height / 128).max(1);
+        let line_height = (GLYPH_HEIGHT + 2) * scale;
+        let margin = scale * 2;
+        let bottom = height.saturating_sub(margin + line_height);
+
+        if self.visible {
+            let status = format!("{} FPS {}%", self.fps, self.speed);
+            draw_text(dst, width, height, &status, margin, margin, scale, color, background);
+
+            let keys: Vec<String> = (0..16).filter(|&k| keypad[k]).map(|k| format!("{:X}", k)).collect();
+            if !keys.is_empty() {
+                let text = format!("KEYS {}", keys.join(" "));
+                draw_text(dst, width, height, &text, margin, bottom, scale, color, background);
+            }
+        }
+
+        if paused {
+            let text = "PAUSED";
+            let x = width.saturating_sub(margin + text_width(text, scale));
+            draw_text(dst, width, height, text, x, margin, scale, color, background);
+        }
+
+        if let Some((text, shown)) = &self.message {
+            if shown.elapsed() > MESSAGE_TIME {
+                self.message = None;
+            } else {
+                let y = bottom.saturating_sub(line_height);
+                draw_text(dst, width, height, text, margin, y, scale, color, background);
+            }
+        }
+    }
+}
+
+fn text_width(text: &str, scale: usize) -> usize {
+    text.chars().count() * (GLYPH_WIDTH + 1) * scale + scale
+}
+
+// Text on a background box, clipped to the buffer
+#[allow(clippy::too_many_arguments)]
+fn draw_text(dst: &mut [u32], width: usize, height: usize, text: &str,
+             x: usize, y: usize, scale: usize, color: u32, background: u32) {
+
+    let box_width = text_width(text, scale);
+    let box_height = (GLYPH_HEIGHT + 2) * scale;
+
+    for py in y..(y + box_height).min(height) {
+        for px in x..(x + box_width).min(width) {
+            dst[py * width + px] = background;
+        }
+    }
+
+    for (index, c) in text.chars().enumerate() {
+        let c = c.to_ascii_uppercase();
+        let bits = FONT.iter().find(|(g, _)| *g == c).or(FONT.last()).map_or(0, |(_, b)| *b);
+        let glyph_x = x + scale + index * (GLYPH_WIDTH + 1) * scale;
+
+        for row in 0..GLYPH_HEIGHT {
+            for col in 0..GLYPH_WIDTH {
+                let shift = (GLYPH_HEIGHT - 1 - row) * GLYPH_WIDTH + (GLYPH_WIDTH - 1 - col);
+                if bits >> shift & 1 == 0 {
+                    continue;
+                }
+
+                for sy in 0..scale {
+                    for sx in 0..scale {
+                        let (px, py) = (glyph_x + col * scale + sx, y + scale + row * scale + sy);
+                        if px < width && py < height {
+                            dst[py * width + px] = color;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
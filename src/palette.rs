@@ -0,0 +1,51 @@
+// Display colours, indexed by the bitplanes set on a pixel
+// 0 = background, 1 = plane 1, 2 = plane 2, 3 = both (XO-CHIP)
+// Up to 16 colours for four planes
+#[derive(Clone, PartialEq, Debug)]
+pub struct Palette {
+    pub name: String,
+    pub colors: Vec<u32>
+}
+
+// Built-in themes: background, plane 1, plane 2, both
+const THEMES: [(&str, [u32; 4]); 5] = [
+    ("mono", [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555]),
+    ("green", [0x0A140A, 0x33FF66, 0x1F8F3F, 0x99FFB3]),
+    ("amber", [0x140C00, 0xFFB000, 0x996A00, 0xFFD980]),
+    ("lcd", [0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F]),
+    ("octo", [0x996600, 0xFFCC00, 0xFF6600, 0x662200])
+];
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::theme("mono").unwrap()
+    }
+}
+
+impl Palette {
+    // Built-in theme by name
+    pub fn theme(name: &str) -> Option<Palette> {
+        THEMES.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(n, colors)| Palette { name: String::from(*n), colors: colors.to_vec() })
+    }
+
+    pub fn themes() -> Vec<Palette> {
+        THEMES.iter()
+            .map(|(n, colors)| Palette { name: String::from(*n), colors: colors.to_vec() })
+            .collect()
+    }
+
+    // Colour for a combination of planes
+    // Short palettes repeat, a 2 colour palette draws every plane the same
+    pub fn color(&self, planes: usize) -> u32 {
+        match planes {
+            0 => self.colors[0],
+            _ => self.colors[1 + (planes - 1) % (self.colors.len() - 1)]
+        }
+    }
+
+    pub fn background(&self) -> u32 {
+        self.color(0)
+    }
+}
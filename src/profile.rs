@@ -0,0 +1,141 @@
+// Execution counts and memory use per address, for ROM developers
+// The report lists the hotspots, ROM bytes that never ran, writes to bytes
+// that also ran as code, then the whole ROM disassembled with the counts
+
+use crate::cpu::{PROGRAM_START, RAM_SIZE};
+use crate::disasm::disassemble;
+
+const HOTSPOTS: usize = 20;
+
+// Per byte flags
+const EXECUTED: u8 = 1;
+const READ: u8 = 2;
+const WRITTEN: u8 = 4;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Access {
+    Read,
+    Write
+}
+
+pub struct Profiler {
+    rom_size: usize,
+    runs: Vec<u64>, // Instructions started at each address
+    flags: Vec<u8>,
+    writes: Vec<u32>,
+    last_writer: Vec<usize> // PC of the latest write to each address
+}
+
+impl Profiler {
+    pub fn new(rom_size: usize) -> Profiler {
+        Profiler {
+            rom_size: rom_size.min(RAM_SIZE - PROGRAM_START),
+            runs: vec![0; RAM_SIZE],
+            flags: vec![0; RAM_SIZE],
+            writes: vec![0; RAM_SIZE],
+            last_writer: vec![0; RAM_SIZE]
+        }
+    }
+
+    // An instruction at pc is about to run
+    pub fn execute(&mut self, pc: usize) {
+        self.runs[pc] += 1;
+        self.flags[pc] |= EXECUTED;
+        self.flags[pc + 1] |= EXECUTED;
+    }
+
+    // The instruction at pc reads or writes len bytes from start
+    pub fn access(&mut self, pc: usize, access: Access, start: usize, len: usize) {
+        for address in start..(start + len).min(RAM_SIZE) {
+            match access {
+                Access::Read => self.flags[address] |= READ,
+                Access::Write => {
+                    self.flags[address] |= WRITTEN;
+                    self.writes[address] += 1;
+                    self.last_writer[address] = pc;
+                }
+            }
+        }
+    }
+
+    pub fn report(&self, ram: &[u8]) -> String {
+        let total: u64 = self.runs.iter().sum();
+        let rom = PROGRAM_START..PROGRAM_START + self.rom_size;
+        let opcode = |address: usize| (ram[address] as u16) << 8 | ram.get(address + 1).copied().unwrap_or(0) as u16;
+
+        let mut report = format!("{} instructions executed at {} addresses\n",
+            total, self.runs.iter().filter(|&&r| r > 0).count());
+
+        let mut hotspots: Vec<usize> = (0..RAM_SIZE).filter(|&a| self.runs[a] > 0).collect();
+        hotspots.sort_by_key(|&a| std::cmp::Reverse(self.runs[a]));
+        let lines: Vec<String> = hotspots.iter().take(HOTSPOTS).map(|&a| {
+            format!("  {:#05X}  {:>10}  {:>5.1}%  {:04X}  {}", a, self.runs[a],
+                self.runs[a] as f64 * 100.0 / total as f64, opcode(a), disassemble(opcode(a)))
+        }).collect();
+        section(&mut report, "Hotspots", &lines);
+
+        // Runs of ROM bytes that never ran, split where they start or stop
+        // being used as data
+        let mut lines = Vec::new();
+        let mut address = rom.start;
+        while address < rom.end {
+            if self.flags[address] & EXECUTED != 0 {
+                address += 1;
+                continue;
+            }
+            let start = address;
+            let data = self.flags[start] & (READ | WRITTEN) != 0;
+            while address < rom.end && self.flags[address] & EXECUTED == 0
+                && (self.flags[address] & (READ | WRITTEN) != 0) == data {
+                address += 1;
+            }
+            lines.push(format!("  {:#05X}-{:#05X}  {:>4} bytes{}", start, address - 1, address - start,
+                if data { "  (used as data)" } else { "" }));
+        }
+        section(&mut report, "Never executed", &lines);
+
+        let lines: Vec<String> = (0..RAM_SIZE)
+            .filter(|&a| self.flags[a] & (EXECUTED | WRITTEN) == EXECUTED | WRITTEN)
+            .map(|a| format!("  {:#05X}  written {} times, last by {:#05X}", a, self.writes[a], self.last_writer[a]))
+            .collect();
+        section(&mut report, "Self-modifying writes", &lines);
+
+        // Whole ROM, instructions where they ran, data bytes where they were
+        // read or written, the rest decoded as if it were code
+        let mut lines = vec![String::from("  flags: x executed, r read, w written")];
+        let mut address = rom.start;
+        while address < rom.end {
+            let flags = self.flags[address];
+            let marks = format!("{}{}{}",
+                if flags & EXECUTED != 0 { 'x' } else { '-' },
+                if flags & READ != 0 { 'r' } else { '-' },
+                if flags & WRITTEN != 0 { 'w' } else { '-' });
+
+            let is_code = self.runs[address] > 0
+                || (flags & (READ | WRITTEN) == 0 && address + 1 < rom.end && self.runs[address + 1] == 0);
+            if is_code {
+                let runs = if self.runs[address] > 0 { self.runs[address].to_string() } else { String::new() };
+                lines.push(format!("  {:#05X}  {:04X}  {:<18} {}  {:>10}", address, opcode(address),
+                    disassemble(opcode(address)), marks, runs).trim_end().to_string());
+                address += 2;
+            } else {
+                lines.push(format!("  {:#05X}  {:02X}    {:<18} {}", address, ram[address],
+                    format!("DB {:#04X}", ram[address]), marks));
+                address += 1;
+            }
+        }
+        section(&mut report, "Disassembly", &lines);
+
+        report
+    }
+}
+
+fn section(report: &mut String, title: &str, lines: &[String]) {
+    *report += &format!("\n{}\n", title);
+    if lines.is_empty() {
+        *report += "  none\n";
+    }
+    for line in lines {
+        *report += &format!("{}\n", line);
+    }
+}
@@ -0,0 +1,154 @@
+// Python module, built with --features python
+// No window or audio, the script drives the frames
+//
+//     import chip8, numpy as np
+//     m = chip8.Machine(open("pong.ch8", "rb").read(), quirks="vip")
+//     m.step(keys=[1], frames=4)
+//     screen = np.frombuffer(m.observation(), np.uint8).reshape(m.height, m.width)
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::cpu::CPU;
+use crate::quirks::Quirks;
+
+#[pyclass(name = "Machine", module = "chip8")]
+pub struct Machine {
+    rom: Vec<u8>,
+    quirks: Quirks,
+    speed: usize,
+    seed: u64,
+    cpu: CPU
+}
+
+impl Machine {
+    fn boot(&self) -> PyResult<CPU> {
+        let mut cpu = CPU::new();
+        cpu.set_quirks(self.quirks);
+        cpu.set_speed(self.speed);
+        cpu.seed_rng(self.seed);
+        cpu.load_program(&self.rom).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(cpu)
+    }
+}
+
+#[pymethods]
+impl Machine {
+    #[new]
+    #[pyo3(signature = (rom, quirks = "default", speed = 12, seed = 0))]
+    fn new(rom: Vec<u8>, quirks: &str, speed: usize, seed: u64) -> PyResult<Machine> {
+        let quirks = Quirks::preset(quirks).ok_or_else(|| PyValueError::new_err(format!("Unknown quirk preset '{}'", quirks)))?;
+        if speed == 0 {
+            return Err(PyValueError::new_err("Speed must be at least 1 instruction per frame"));
+        }
+
+        let mut machine = Machine { rom, quirks, speed, seed, cpu: CPU::new() };
+        machine.cpu = machine.boot()?;
+        Ok(machine)
+    }
+
+    // Restart the ROM, optionally with a new seed
+    #[pyo3(signature = (seed = None))]
+    fn reset(&mut self, seed: Option<u64>) -> PyResult<()> {
+        if let Some(seed) = seed {
+            self.seed = seed;
+        }
+        self.cpu = self.boot()?;
+        Ok(())
+    }
+
+    // Hold keys for some frames, keys is a bitmask (bit n = key n) or a
+    // list of key numbers
+    // Returns whether the beeper sounded
+    #[pyo3(signature = (keys = None, frames = 1))]
+    fn step(&mut self, keys: Option<&Bound<'_, PyAny>>, frames: u32) -> PyResult<bool> {
+        let keypad = match keys {
+            Some(keys) => keypad(keys)?,
+            None => [false; 16]
+        };
+
+        let mut beeped = false;
+        for _ in 0..frames {
+            beeped |= self.cpu.run_frame(keypad).map_err(PyRuntimeError::new_err)?;
+        }
+        Ok(beeped)
+    }
+
+    // Screen as width * height bytes, row by row, 0 = off, else the lit planes
+    fn observation<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, self.cpu.display().pixels())
+    }
+
+    #[getter]
+    fn width(&self) -> usize {
+        self.cpu.display().width()
+    }
+
+    #[getter]
+    fn height(&self) -> usize {
+        self.cpu.display().height()
+    }
+
+    #[getter]
+    fn frames(&self) -> u64 {
+        self.cpu.frames()
+    }
+
+    // All 4096 bytes of memory
+    #[getter]
+    fn ram<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, self.cpu.ram())
+    }
+
+    fn read_ram<'py>(&self, py: Python<'py>, address: usize, length: usize) -> PyResult<Bound<'py, PyBytes>> {
+        let ram = self.cpu.ram();
+        match ram.get(address..address.saturating_add(length)) {
+            Some(bytes) => Ok(PyBytes::new(py, bytes)),
+            None => Err(PyValueError::new_err(format!("{:#05X}+{} is outside memory", address, length)))
+        }
+    }
+
+    fn write_ram(&mut self, address: usize, data: Vec<u8>) -> PyResult<()> {
+        let ram = self.cpu.ram_mut();
+        match ram.get_mut(address..address.saturating_add(data.len())) {
+            Some(bytes) => {
+                bytes.copy_from_slice(&data);
+                Ok(())
+            }
+            None => Err(PyValueError::new_err(format!("{:#05X}+{} is outside memory", address, data.len())))
+        }
+    }
+
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.cpu.save_state())
+    }
+
+    fn load_state(&mut self, state: Vec<u8>) -> PyResult<()> {
+        self.cpu.load_state(&state).map_err(PyValueError::new_err)
+    }
+}
+
+fn keypad(keys: &Bound<'_, PyAny>) -> PyResult<[bool; 16]> {
+    let mut keypad = [false; 16];
+
+    if let Ok(mask) = keys.extract::<u16>() {
+        for (key, down) in keypad.iter_mut().enumerate() {
+            *down = mask >> key & 1 == 1;
+        }
+        return Ok(keypad);
+    }
+
+    for key in keys.extract::<Vec<usize>>()? {
+        match keypad.get_mut(key) {
+            Some(down) => *down = true,
+            None => {return Err(PyValueError::new_err(format!("Invalid key {}, expected 0 to 15", key)));}
+        }
+    }
+    Ok(keypad)
+}
+
+#[pymodule]
+fn chip8(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Machine>()
+}
@@ -0,0 +1,74 @@
+use crate::display::SpriteMode;
+
+// Behaviour differences between chip8 interpreters
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Quirks {
+    // 8xy1, 8xy2, 8xy3 reset VF to 0
+    pub vf_reset: bool,
+    // 8xy6, 8xyE shift Vy into Vx instead of shifting Vx in place
+    pub shift_vy: bool,
+    // Fx55, Fx65 leave I = I + x + 1
+    pub memory_increment: bool,
+    // Bnnn jumps to nnn + Vx (Bxnn) instead of nnn + V0
+    pub jump_vx: bool,
+    // Sprites wrap or clip at the screen edges
+    pub sprite_mode: SpriteMode
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            shift_vy: false,
+            memory_increment: false,
+            jump_vx: false,
+            sprite_mode: SpriteMode::Wrap
+        }
+    }
+}
+
+impl Quirks {
+    // Original COSMAC VIP interpreter
+    pub fn vip() -> Quirks {
+        Quirks {
+            vf_reset: true,
+            shift_vy: true,
+            memory_increment: true,
+            jump_vx: false,
+            sprite_mode: SpriteMode::Clip
+        }
+    }
+
+    // SUPER-CHIP 1.1
+    pub fn schip() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            shift_vy: false,
+            memory_increment: false,
+            jump_vx: true,
+            sprite_mode: SpriteMode::Clip
+        }
+    }
+
+    // XO-CHIP (Octo)
+    pub fn xochip() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            shift_vy: true,
+            memory_increment: true,
+            jump_vx: false,
+            sprite_mode: SpriteMode::Wrap
+        }
+    }
+
+    // Preset by name
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name.to_ascii_lowercase().as_str() {
+            "default" => Some(Quirks::default()),
+            "chip8" | "vip" => Some(Quirks::vip()),
+            "schip" | "superchip" => Some(Quirks::schip()),
+            "xochip" | "xo-chip" => Some(Quirks::xochip()),
+            _ => None
+        }
+    }
+}
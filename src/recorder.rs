@@ -0,0 +1,198 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::audio::WavExport;
+use crate::synth::Tone;
+
+// Frames per second of the emulated display
+const FPS: u64 = 60;
+
+// Records presented frames
+// .gif: animated GIF, identical frames are merged and timing is kept in
+//       GIF centiseconds (frames shorter than 2cs are dropped, players
+//       slow down anything faster)
+// .y4m: raw 4:4:4 video plus a .wav with the beeper, for ffmpeg
+pub struct Recorder {
+    path: PathBuf,
+    width: usize,
+    height: usize,
+    frames: u64,
+    output: Output
+}
+
+enum Output {
+    Gif {
+        encoder: Option<gif::Encoder<BufWriter<File>>>,
+        pending: Option<(Vec<u32>, u64)>, // Frame waiting for its delay, start time in cs
+    },
+    Y4m {
+        video: BufWriter<File>,
+        audio: WavExport
+    }
+}
+
+impl Recorder {
+    // Format chosen by the file extension, tone is the beeper for the .wav
+    pub fn start(path: &Path, width: usize, height: usize, tone: Tone) -> Result<Recorder, String> {
+        let error = |err: &dyn std::fmt::Display| format!("{}: {}", path.display(), err);
+
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+
+        let output = match extension.as_str() {
+            "gif" => {
+                let file = File::create(path).map_err(|e| error(&e))?;
+                let mut encoder = gif::Encoder::new(BufWriter::new(file), width as u16, height as u16, &[])
+                    .map_err(|e| error(&e))?;
+                encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| error(&e))?;
+
+                Output::Gif { encoder: Some(encoder), pending: None }
+            }
+            "y4m" => {
+                let file = File::create(path).map_err(|e| error(&e))?;
+                let mut video = BufWriter::new(file);
+                writeln!(video, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, FPS).map_err(|e| error(&e))?;
+
+                let audio = WavExport::create(&path.with_extension("wav"), tone)?;
+
+                Output::Y4m { video, audio }
+            }
+            _ => {return Err(format!("{}: expected a .gif or .y4m file", path.display()));}
+        };
+
+        Ok(Recorder { path: path.to_path_buf(), width, height, frames: 0, output })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Add a presented frame and whether the beeper was on during it
+    pub fn frame(&mut self, pixels: &[u32], width: usize, height: usize, beeping: bool) -> Result<(), String> {
+        if (width, height) != (self.width, self.height) {
+            return Err(format!("Resolution changed to {}x{}", width, height));
+        }
+
+        let path = self.path.display().to_string();
+        let error = |err: &dyn std::fmt::Display| format!("{}: {}", path, err);
+        let time = self.frames * 100 / FPS; // Centiseconds
+        self.frames += 1;
+
+        match &mut self.output {
+            Output::Gif { encoder, pending } => {
+                let encoder = match encoder {
+                    Some(e) => e,
+                    None => {return Ok(());}
+                };
+
+                match pending {
+                    // Same picture, or too soon for a new GIF frame: keep the latest
+                    Some((buffer, start)) if buffer == pixels || time - *start < 2 => {
+                        buffer.copy_from_slice(pixels);
+                    }
+                    _ => {
+                        if let Some((buffer, start)) = pending.take() {
+                            write_gif_frame(encoder, &buffer, width, height, time - start).map_err(|e| error(&e))?;
+                        }
+                        *pending = Some((pixels.to_vec(), time));
+                    }
+                }
+            }
+
+            Output::Y4m { video, audio } => {
+                video.write_all(b"FRAME\n").map_err(|e| error(&e))?;
+                video.write_all(&yuv444(pixels)).map_err(|e| error(&e))?;
+                audio.frame(beeping)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Flush the last frame and close the files
+    pub fn finish(&mut self) -> Result<(), String> {
+        let path = self.path.display().to_string();
+        let error = |err: &dyn std::fmt::Display| format!("{}: {}", path, err);
+        let end = self.frames * 100 / FPS;
+
+        match &mut self.output {
+            Output::Gif { encoder, pending } => {
+                if let Some(mut encoder) = encoder.take() {
+                    if let Some((buffer, start)) = pending.take() {
+                        write_gif_frame(&mut encoder, &buffer, self.width, self.height, (end - start).max(2))
+                            .map_err(|e| error(&e))?;
+                    }
+                    encoder.into_inner().map_err(|e| error(&e))?;
+                }
+            }
+
+            Output::Y4m { video, audio } => {
+                video.flush().map_err(|e| error(&e))?;
+                audio.finish()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            eprintln!("Could not finish recording: {}", err);
+        }
+    }
+}
+
+// Indexed frame with a local palette of the colours in use
+fn write_gif_frame(encoder: &mut gif::Encoder<BufWriter<File>>, pixels: &[u32],
+                   width: usize, height: usize, delay: u64) -> Result<(), gif::EncodingError> {
+    let mut colors: Vec<u32> = Vec::new();
+    let mut indices = Vec::with_capacity(pixels.len());
+
+    for &px in pixels {
+        let index = match colors.iter().position(|&c| c == px) {
+            Some(i) => i,
+            None => {
+                colors.push(px);
+                colors.len() - 1
+            }
+        };
+        // Only 256 colours fit, extras share the last entry
+        indices.push(index.min(255) as u8);
+    }
+
+    let palette: Vec<u8> = colors.iter().take(256)
+        .flat_map(|&c| [(c >> 16) as u8, (c >> 8) as u8, c as u8])
+        .collect();
+
+    let frame = gif::Frame {
+        width: width as u16,
+        height: height as u16,
+        delay: delay.min(u16::MAX as u64) as u16,
+        palette: Some(palette),
+        buffer: indices.into(),
+        ..gif::Frame::default()
+    };
+
+    encoder.write_frame(&frame)
+}
+
+// Y, U and V planes, BT.601 limited range
+fn yuv444(pixels: &[u32]) -> Vec<u8> {
+    let mut planes = vec![0u8; pixels.len() * 3];
+    let (y_plane, rest) = planes.split_at_mut(pixels.len());
+    let (u_plane, v_plane) = rest.split_at_mut(pixels.len());
+
+    for (index, &px) in pixels.iter().enumerate() {
+        let r = ((px >> 16) & 0xFF) as f32;
+        let g = ((px >> 8) & 0xFF) as f32;
+        let b = (px & 0xFF) as f32;
+
+        y_plane[index] = (16.0 + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8;
+        u_plane[index] = (128.0 - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8;
+        v_plane[index] = (128.0 + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8;
+    }
+
+    planes
+}
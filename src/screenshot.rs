@@ -0,0 +1,63 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Write 0xRRGGBB pixels to an RGB PNG
+pub fn save_png(path: &Path, pixels: &[u32], width: usize, height: usize) -> Result<(), String> {
+    let file = File::create(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let data: Vec<u8> = pixels.iter()
+        .flat_map(|&px| [(px >> 16) as u8, (px >> 8) as u8, px as u8])
+        .collect();
+
+    encoder.write_header()
+        .and_then(|mut writer| writer.write_image_data(&data))
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+// Nearest neighbour upscale by a whole factor
+pub fn upscale(pixels: &[u32], width: usize, height: usize, factor: usize) -> Vec<u32> {
+    let mut scaled = Vec::with_capacity(width * height * factor * factor);
+
+    for y in 0..height * factor {
+        let row = &pixels[(y / factor) * width..(y / factor + 1) * width];
+        for x in 0..width * factor {
+            scaled.push(row[x / factor]);
+        }
+    }
+
+    scaled
+}
+
+// dir/chip8-YYYYMMDD-HHMMSS-mmm<suffix>.<extension>, in UTC
+pub fn timestamped_path(dir: &Path, suffix: &str, extension: &str) -> PathBuf {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+
+    dir.join(format!("chip8-{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}{}.{}",
+        year, month, day,
+        secs / 3600 % 24, secs / 60 % 60, secs % 60, now.subsec_millis(),
+        suffix, extension))
+}
+
+// Days since 1970-01-01 to (year, month, day)
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+// Emulated frames per second, each one is SAMPLE_RATE / FPS samples long
+pub const FPS: u32 = 60;
+
+// Shape of the beeper tone
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Waveform {
+    Square, // Like the original hardware
+    Sine,
+    Triangle,
+    Sawtooth
+}
+
+impl Waveform {
+    pub fn parse(name: &str) -> Option<Waveform> {
+        match name.to_ascii_lowercase().as_str() {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" | "saw" => Some(Waveform::Sawtooth),
+            _ => None
+        }
+    }
+
+    // Level in -1..1 at a phase in 0..1
+    pub fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (phase * std::f32::consts::TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0
+        }
+    }
+}
+
+// Beeper settings
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Tone {
+    pub frequency: f32, // Hz
+    pub waveform: Waveform,
+    pub volume: f32, // 0..1
+    pub attack: Duration, // Fade in when the sound timer starts
+    pub release: Duration // Fade out when it stops
+}
+
+impl Default for Tone {
+    fn default() -> Tone {
+        Tone {
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+            attack: Duration::from_millis(5),
+            release: Duration::from_millis(5)
+        }
+    }
+}
+
+// Tone generator with the envelope, shared by the live output, the WAV
+// files and the libretro core so they all sound the same
+pub struct Synth {
+    tone: Tone,
+    phase: f32,
+    gain: f32
+}
+
+impl Synth {
+    pub fn new(tone: Tone) -> Synth {
+        Synth { tone, phase: 0.0, gain: 0.0 }
+    }
+
+    // Next sample, fading towards on or off
+    pub fn sample(&mut self, on: bool) -> f32 {
+        let (target, fade) = if on {
+            (1.0, self.tone.attack)
+        } else {
+            (0.0, self.tone.release)
+        };
+
+        // Linear ramp, a zero length fade switches at once
+        let step = 1.0 / (fade.as_secs_f32() * SAMPLE_RATE as f32).max(1.0);
+        self.gain = if self.gain < target { (self.gain + step).min(target) } else { (self.gain - step).max(target) };
+
+        let level = self.tone.waveform.sample(self.phase) * self.tone.volume * self.gain;
+        self.phase = (self.phase + self.tone.frequency / SAMPLE_RATE as f32).fract();
+
+        level
+    }
+
+    // Samples in an emulated frame, spread so every second has exactly
+    // SAMPLE_RATE of them
+    pub fn frame_samples(frame: u64) -> u64 {
+        let rate = SAMPLE_RATE as u64;
+        let fps = FPS as u64;
+        (frame + 1) * rate / fps - frame * rate / fps
+    }
+}
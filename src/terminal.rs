@@ -0,0 +1,303 @@
+use crossterm::event::{
+    self,
+    Event,
+    KeyCode,
+    KeyEventKind,
+    KeyModifiers,
+    KeyboardEnhancementFlags,
+    PushKeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags
+};
+use crossterm::{cursor, execute, terminal};
+use minifb::Key;
+
+use std::io::{self, Stdout, Write};
+use std::time::Duration;
+
+use crate::display::Display;
+use crate::frontend::Frontend;
+use crate::keymap::{self, Keymap};
+use crate::palette::Palette;
+
+// Without key release events a key counts as held for this many frames
+// after its last press or repeat
+const KEY_HOLD_FRAMES: u32 = 8;
+
+// How pixels are packed into characters
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TerminalMode {
+    // ▀ with the top pixel as foreground and the bottom one as background
+    HalfBlock,
+    // 2x4 braille dots per character, one colour per character
+    Braille
+}
+
+impl TerminalMode {
+    pub fn parse(name: &str) -> Option<TerminalMode> {
+        match name.to_ascii_lowercase().as_str() {
+            "halfblock" | "half-block" => Some(TerminalMode::HalfBlock),
+            "braille" => Some(TerminalMode::Braille),
+            _ => None
+        }
+    }
+}
+
+// Frontend drawing with ANSI colours, for use over SSH
+pub struct Terminal {
+    out: Stdout,
+    mode: TerminalMode,
+    palettes: Vec<Palette>, // Selected palette first, then the other themes
+    palette_index: usize,
+    keymap: Keymap,
+    held: Vec<(Key, u32)>, // Held keys and frames left, u32::MAX until released
+    release_events: bool,
+    open: bool,
+    paused: bool,
+    last_frame: String
+}
+
+impl Terminal {
+    // Switch to raw mode on the alternate screen
+    pub fn new(mode: TerminalMode) -> io::Result<Terminal> {
+        let mut out = io::stdout();
+
+        terminal::enable_raw_mode()?;
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+
+        // Terminals with the kitty keyboard protocol report releases
+        let release_events = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if release_events {
+            execute!(out, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+
+        Ok(Terminal {
+            out,
+            mode,
+            palettes: Palette::themes(),
+            palette_index: 0,
+            keymap: Keymap::default(),
+            held: Vec::new(),
+            release_events,
+            open: true,
+            paused: false,
+            last_frame: String::new()
+        })
+    }
+
+    // Use a palette, the built-in themes follow it when cycling
+    pub fn set_palette(&mut self, palette: Palette) {
+        let mut palettes = vec![palette.clone()];
+        palettes.extend(Palette::themes().into_iter().filter(|p| p.name != palette.name));
+
+        self.palettes = palettes;
+        self.palette_index = 0;
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    // Drain pending terminal events into the held keys
+    fn poll_input(&mut self) {
+        // Age keys that never get a release event
+        for (_, frames) in self.held.iter_mut() {
+            if *frames != u32::MAX {
+                *frames = frames.saturating_sub(1);
+            }
+        }
+        self.held.retain(|&(_, frames)| frames > 0);
+
+        while let Ok(true) = event::poll(Duration::ZERO) {
+            let key_event = match event::read() {
+                Ok(Event::Key(k)) => k,
+                Ok(_) => continue,
+                Err(_) => break
+            };
+
+            let ctrl_c = key_event.code == KeyCode::Char('c') && key_event.modifiers.contains(KeyModifiers::CONTROL);
+            if key_event.code == KeyCode::Esc || ctrl_c {
+                self.open = false;
+                return;
+            }
+
+            if key_event.code == KeyCode::F(2) && key_event.kind == KeyEventKind::Press {
+                self.palette_index = (self.palette_index + 1) % self.palettes.len();
+                continue;
+            }
+
+            if key_event.code == KeyCode::F(4) && key_event.kind == KeyEventKind::Press {
+                self.paused = !self.paused;
+                continue;
+            }
+
+            let key = match to_key(key_event.code) {
+                Some(k) => k,
+                None => continue
+            };
+
+            self.held.retain(|&(k, _)| k != key);
+            match key_event.kind {
+                KeyEventKind::Release => (),
+                _ if self.release_events => self.held.push((key, u32::MAX)),
+                _ => self.held.push((key, KEY_HOLD_FRAMES))
+            }
+        }
+    }
+
+    // One line per two pixel rows
+    fn render_half_blocks(&self, display: &Display, frame: &mut String) {
+        let palette = &self.palettes[self.palette_index];
+        let (width, height) = (display.width(), display.height());
+        let pixels = display.pixels();
+
+        for y in (0..height).step_by(2) {
+            let mut colors: Option<(u32, u32)> = None;
+
+            for x in 0..width {
+                let top = palette.color(pixels[y * width + x] as usize);
+                let bottom = if y + 1 < height { palette.color(pixels[(y + 1) * width + x] as usize) } else { palette.background() };
+
+                if colors != Some((top, bottom)) {
+                    frame.push_str(&format!("{}{}", fg(top), bg(bottom)));
+                    colors = Some((top, bottom));
+                }
+                frame.push('▀');
+            }
+
+            frame.push_str("\x1b[0m\r\n");
+        }
+    }
+
+    // One line per four pixel rows
+    fn render_braille(&self, display: &Display, frame: &mut String) {
+        // Dot bits for (x, y) inside a 2x4 cell
+        const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+        let palette = &self.palettes[self.palette_index];
+        let (width, height) = (display.width(), display.height());
+        let pixels = display.pixels();
+
+        for cell_y in (0..height).step_by(4) {
+            let mut color: Option<u32> = None;
+
+            for cell_x in (0..width).step_by(2) {
+                let mut bits = 0;
+                let mut planes = 0;
+
+                for (dx, column) in DOTS.iter().enumerate() {
+                    for (dy, &bit) in column.iter().enumerate() {
+                        let (x, y) = (cell_x + dx, cell_y + dy);
+                        if x < width && y < height && pixels[y * width + x] != 0 {
+                            bits |= bit;
+                            planes = planes.max(pixels[y * width + x]);
+                        }
+                    }
+                }
+
+                let cell_color = palette.color(planes.max(1) as usize);
+                if color != Some(cell_color) {
+                    frame.push_str(&format!("{}{}", fg(cell_color), bg(palette.background())));
+                    color = Some(cell_color);
+                }
+                frame.push(char::from_u32(0x2800 + bits).unwrap_or(' '));
+            }
+
+            frame.push_str("\x1b[0m\r\n");
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.release_events {
+            let _ = execute!(self.out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.out, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+impl Frontend for Terminal {
+    // Escape or Ctrl+C quits
+    fn is_open(&self) -> bool {
+        self.open
+    }
+
+    // Hotkeys arrive with the key events
+    fn handle_hotkeys(&mut self) {
+        self.poll_input();
+    }
+
+    fn handle_key_events(&mut self) -> [bool; 16] {
+        let pressed: Vec<Key> = self.held.iter().map(|&(k, _)| k).collect();
+        self.keymap.keypad(&pressed)
+    }
+
+    fn paused(&self) -> bool {
+        self.paused
+    }
+
+    // Only redraws when the picture changed, to spare slow links
+    fn refresh(&mut self, display: &Display, _beeping: bool, _frames: u64) {
+        let mut frame = String::from("\x1b[H");
+
+        match self.mode {
+            TerminalMode::HalfBlock => self.render_half_blocks(display, &mut frame),
+            TerminalMode::Braille => self.render_braille(display, &mut frame)
+        }
+        frame.push_str(if self.paused { "PAUSED" } else { "      " });
+
+        if frame != self.last_frame {
+            // Clear anything printed before the first frame
+            if self.last_frame.is_empty() {
+                let _ = self.out.write_all(b"\x1b[2J");
+            }
+            let _ = self.out.write_all(frame.as_bytes());
+            let _ = self.out.flush();
+            self.last_frame = frame;
+        }
+    }
+}
+
+fn fg(color: u32) -> String {
+    format!("\x1b[38;2;{};{};{}m", (color >> 16) & 0xFF, (color >> 8) & 0xFF, color & 0xFF)
+}
+
+fn bg(color: u32) -> String {
+    format!("\x1b[48;2;{};{};{}m", (color >> 16) & 0xFF, (color >> 8) & 0xFF, color & 0xFF)
+}
+
+// Terminal key to the minifb key the keymap uses
+fn to_key(code: KeyCode) -> Option<Key> {
+    match code {
+        KeyCode::Char(c) if c.is_ascii_digit() => keymap::parse_key(&format!("Key{}", c)),
+        KeyCode::Char(c) if c.is_ascii_alphabetic() => keymap::parse_key(&c.to_string()),
+        KeyCode::Char(' ') => Some(Key::Space),
+        KeyCode::Char(',') => Some(Key::Comma),
+        KeyCode::Char('.') => Some(Key::Period),
+        KeyCode::Char('/') => Some(Key::Slash),
+        KeyCode::Char(';') => Some(Key::Semicolon),
+        KeyCode::Char('\'') => Some(Key::Apostrophe),
+        KeyCode::Char('[') => Some(Key::LeftBracket),
+        KeyCode::Char(']') => Some(Key::RightBracket),
+        KeyCode::Char('-') => Some(Key::Minus),
+        KeyCode::Char('=') => Some(Key::Equal),
+        KeyCode::Char('`') => Some(Key::Backquote),
+        KeyCode::Char('\\') => Some(Key::Backslash),
+        KeyCode::Up => Some(Key::Up),
+        KeyCode::Down => Some(Key::Down),
+        KeyCode::Left => Some(Key::Left),
+        KeyCode::Right => Some(Key::Right),
+        KeyCode::Enter => Some(Key::Enter),
+        KeyCode::Tab => Some(Key::Tab),
+        KeyCode::Backspace => Some(Key::Backspace),
+        KeyCode::Delete => Some(Key::Delete),
+        KeyCode::Insert => Some(Key::Insert),
+        KeyCode::Home => Some(Key::Home),
+        KeyCode::End => Some(Key::End),
+        KeyCode::PageUp => Some(Key::PageUp),
+        KeyCode::PageDown => Some(Key::PageDown),
+        KeyCode::F(n) => keymap::parse_key(&format!("F{}", n)),
+        _ => None
+    }
+}
@@ -0,0 +1,176 @@
+// Per-instruction execution trace
+//
+//      cycle  frame  PC     op    instruction        changes
+//        412     34  0x21A  D015  DRW V0, V1, 5      VF=0x00
+//
+// The last HISTORY_SIZE instructions are always kept, whatever the address
+// range, so they can be dumped when the machine faults
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
+
+use crate::disasm::disassemble;
+
+const HISTORY_SIZE: usize = 1000;
+
+// What an instruction can change besides PC
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct Registers {
+    pub v: [u8; 16],
+    pub i: usize,
+    pub sp: usize,
+    pub dt: u8,
+    pub st: u8
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TraceEntry {
+    pub cycle: u64, // Instructions executed before this one
+    pub frame: u64,
+    pub pc: usize,
+    pub opcode: u16,
+    pub before: Registers,
+    pub after: Registers
+}
+
+impl TraceEntry {
+    // Registers that differ after the instruction, like "V0=0x05 I=0x2A0"
+    pub fn changes(&self) -> String {
+        let (before, after) = (&self.before, &self.after);
+        let mut changes = Vec::new();
+
+        for (index, (old, new)) in before.v.iter().zip(after.v.iter()).enumerate() {
+            if old != new {
+                changes.push(format!("V{:X}={:#04X}", index, new));
+            }
+        }
+        if before.i != after.i {
+            changes.push(format!("I={:#05X}", after.i));
+        }
+        if before.sp != after.sp {
+            changes.push(format!("SP={}", after.sp));
+        }
+        if before.dt != after.dt {
+            changes.push(format!("DT={}", after.dt));
+        }
+        if before.st != after.st {
+            changes.push(format!("ST={}", after.st));
+        }
+
+        changes.join(" ")
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let line = format!("{:>10} {:>6}  {:#05X}  {:04X}  {:<18} {}",
+            self.cycle, self.frame, self.pc, self.opcode, disassemble(self.opcode), self.changes());
+        write!(f, "{}", line.trim_end())
+    }
+}
+
+pub struct Tracer {
+    output: Option<Box<dyn Write + Send + Sync>>,
+    range: Option<(usize, usize)>, // Inclusive PC range written to output
+    history: VecDeque<TraceEntry>,
+    error: Option<String> // First write error, output stops after it
+}
+
+// "200-2FF", hex with or without 0x
+pub fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (start, end) = text.split_once('-')?;
+    let hex = |t: &str| usize::from_str_radix(t.trim().trim_start_matches("0x").trim_start_matches("0X"), 16).ok();
+    let (start, end) = (hex(start)?, hex(end)?);
+    (start <= end).then_some((start, end))
+}
+
+impl Tracer {
+    // Only keeps the history when output is None
+    pub fn new(output: Option<Box<dyn Write + Send + Sync>>, range: Option<(usize, usize)>) -> Tracer {
+        let mut tracer = Tracer {
+            output,
+            range,
+            history: VecDeque::with_capacity(HISTORY_SIZE),
+            error: None
+        };
+        tracer.write_line(&format!("{:>10} {:>6}  {:<5}  {:<4}  {:<18} {}", "cycle", "frame", "PC", "op", "instruction", "changes"));
+        tracer
+    }
+
+    pub fn record(&mut self, entry: TraceEntry) {
+        if self.history.len() == HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.history.push_back(entry);
+
+        if self.output.is_some() && self.range.is_none_or(|(start, end)| (start..=end).contains(&entry.pc)) {
+            self.write_line(&entry.to_string());
+        }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if let Some(output) = &mut self.output
+            && let Err(err) = writeln!(output, "{}", line) {
+            self.error = Some(err.to_string());
+            self.output = None;
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        if let Some(output) = &mut self.output
+            && let Err(err) = output.flush() {
+            self.error = Some(err.to_string());
+        }
+
+        match &self.error {
+            Some(err) => Err(format!("Trace output failed: {}", err)),
+            None => Ok(())
+        }
+    }
+
+    // Oldest first
+    pub fn history(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.history.iter()
+    }
+
+    // The history, then the instruction that faulted, if PC was in memory
+    pub fn dump(&self, error: &str, pc: usize, opcode: Option<u16>) -> String {
+        let mut dump = format!("Last {} instructions:\n", self.history.len());
+        for entry in &self.history {
+            dump += &format!("{}\n", entry);
+        }
+        dump += &format!("{} at {:#05X}", error, pc);
+        if let Some(opcode) = opcode {
+            dump += &format!(": {:04X}  {}", opcode, disassemble(opcode));
+        }
+        dump
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("200-2FF"), Some((0x200, 0x2FF)));
+        assert_eq!(parse_range("0x200-0X2ff"), Some((0x200, 0x2FF)));
+        assert_eq!(parse_range(" 300 - 300 "), Some((0x300, 0x300)));
+
+        for text in ["", "200", "2FF-200", "200-", "-2FF", "20G-2FF", "200-2FF-300"] {
+            assert_eq!(parse_range(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut tracer = Tracer::new(None, None);
+        for cycle in 0..HISTORY_SIZE as u64 + 5 {
+            tracer.record(TraceEntry { cycle, frame: 0, pc: 0x200, opcode: 0x1200, before: Registers::default(), after: Registers::default() });
+        }
+
+        assert_eq!(tracer.history().count(), HISTORY_SIZE);
+        assert_eq!(tracer.history().next().unwrap().cycle, 5);
+    }
+}
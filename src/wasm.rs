@@ -0,0 +1,135 @@
+// C ABI for the browser, driven by web/chip8.js
+// JavaScript owns the frame timing, keyboard and WebAudio, the machine
+// only emulates frames and renders RGBA pixels for a canvas ImageData
+//
+// Strings and ROMs are passed by copying them into memory from chip8_alloc
+
+use crate::cpu::CPU;
+use crate::palette::Palette;
+use crate::quirks::Quirks;
+
+pub struct Machine {
+    cpu: CPU,
+    palette: Palette,
+    rgba: Vec<u8>
+}
+
+// Buffer for JavaScript to write into, released with chip8_dealloc
+#[unsafe(no_mangle)]
+pub extern "C" fn chip8_alloc(len: usize) -> *mut u8 {
+    let mut buffer = vec![0u8; len].into_boxed_slice();
+    let ptr = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    ptr
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_dealloc(ptr: *mut u8, len: usize) {
+    drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)) });
+}
+
+// New machine, JavaScript picks the seed (Math.random)
+#[unsafe(no_mangle)]
+pub extern "C" fn chip8_new(seed: u32) -> *mut Machine {
+    let mut cpu = CPU::new();
+    cpu.seed_rng(seed as u64);
+
+    Box::into_raw(Box::new(Machine { cpu, palette: Palette::default(), rgba: Vec::new() }))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_free(machine: *mut Machine) {
+    drop(unsafe { Box::from_raw(machine) });
+}
+
+// 0 on success, -1 when the ROM does not fit in memory
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_load(machine: *mut Machine, rom: *const u8, len: usize) -> i32 {
+    let machine = unsafe { &mut *machine };
+    let rom = unsafe { std::slice::from_raw_parts(rom, len) };
+
+    match machine.cpu.load_program(rom) {
+        Ok(()) => 0,
+        Err(_) => -1
+    }
+}
+
+// Quirk preset by name, -1 when unknown
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_set_quirks(machine: *mut Machine, name: *const u8, len: usize) -> i32 {
+    let machine = unsafe { &mut *machine };
+
+    match Quirks::preset(unsafe { str_arg(name, len) }) {
+        Some(quirks) => {
+            machine.cpu.set_quirks(quirks);
+            0
+        }
+        None => -1
+    }
+}
+
+// Colour theme by name, -1 when unknown
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_set_theme(machine: *mut Machine, name: *const u8, len: usize) -> i32 {
+    let machine = unsafe { &mut *machine };
+
+    match Palette::theme(unsafe { str_arg(name, len) }) {
+        Some(palette) => {
+            machine.palette = palette;
+            0
+        }
+        None => -1
+    }
+}
+
+// Instructions per frame
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_set_speed(machine: *mut Machine, instructions_per_frame: u32) {
+    let machine = unsafe { &mut *machine };
+    machine.cpu.set_speed((instructions_per_frame as usize).max(1));
+}
+
+// Emulate a frame, keys has bit n set while chip8 key n is down
+// 1 when the beeper sounds during the frame, 0 when silent, -1 on a crash
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_frame(machine: *mut Machine, keys: u32) -> i32 {
+    let machine = unsafe { &mut *machine };
+
+    let mut keypad = [false; 16];
+    for (key, down) in keypad.iter_mut().enumerate() {
+        *down = keys >> key & 1 == 1;
+    }
+
+    match machine.cpu.run_frame(keypad) {
+        Ok(beeping) => beeping as i32,
+        Err(_) => -1
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_width(machine: *const Machine) -> u32 {
+    unsafe { &*machine }.cpu.display().width() as u32
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_height(machine: *const Machine) -> u32 {
+    unsafe { &*machine }.cpu.display().height() as u32
+}
+
+// Render the display with the palette, width * height RGBA pixels
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_pixels(machine: *mut Machine) -> *const u8 {
+    let machine = unsafe { &mut *machine };
+
+    machine.rgba.clear();
+    for &planes in machine.cpu.display().pixels() {
+        let color = machine.palette.color(planes as usize);
+        machine.rgba.extend([(color >> 16) as u8, (color >> 8) as u8, color as u8, 0xFF]);
+    }
+
+    machine.rgba.as_ptr()
+}
+
+unsafe fn str_arg<'a>(ptr: *const u8, len: usize) -> &'a str {
+    std::str::from_utf8(unsafe { std::slice::from_raw_parts(ptr, len) }).unwrap_or("")
+}
@@ -1,128 +1,437 @@
 use minifb::{
     Key,
+    KeyRepeat,
     WindowOptions,
     Scale,
+    ScaleMode,
     Error
 };
 
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+use crate::synth::Tone;
+use crate::display::{Display, WIDTH, HEIGHT};
+use crate::filter::{Filter, FilterMode};
+use crate::frontend::Frontend;
+use crate::keymap::Keymap;
+use crate::overlay::Overlay;
+use crate::palette::Palette;
+use crate::recorder::Recorder;
+use crate::screenshot;
 
-const PX_OFF: u32 = 0x000000;
-const PX_ON: u32 = 0xFFFFFF;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+
+// Hotkeys
+const KEY_NEXT_THEME: Key = Key::F2;
+const KEY_OVERLAY: Key = Key::F3;
+const KEY_PAUSE: Key = Key::F4;
+const KEY_FULLSCREEN: Key = Key::F11;
+const KEY_SCREENSHOT: Key = Key::F12;
+const KEY_RECORD: Key = Key::F10;
+
+// How the image is fitted into the window
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Scaling {
+    // Largest whole multiple that fits, centred
+    Integer,
+    // Largest size keeping the aspect ratio, centred
+    Aspect,
+    // Fill the window
+    Stretch
+}
+
+impl Scaling {
+    pub fn parse(name: &str) -> Option<Scaling> {
+        match name.to_ascii_lowercase().as_str() {
+            "integer" => Some(Scaling::Integer),
+            "aspect" => Some(Scaling::Aspect),
+            "stretch" => Some(Scaling::Stretch),
+            _ => None
+        }
+    }
+}
 
 pub struct Window {
-    win: minifb::Window,
-    framebuffer: [u32; WIDTH * HEIGHT]
-} 
+    win: Option<minifb::Window>, // None when running headless
+    title: String,
+    scale: usize,
+    fullscreen: bool,
+    scaling: Scaling,
+    buffer: Vec<u32>, // Display rendered with the palette
+    buffer_width: usize,
+    buffer_height: usize,
+    scaled: Vec<u32>, // Buffer fitted to the window size
+    palettes: Vec<Palette>, // Selected palette first, then the other themes
+    palette_index: usize,
+    filter: Filter,
+    screenshot_dir: PathBuf, // Screenshots and recordings
+    recorder: Option<Recorder>,
+    record_format: String, // Extension for recordings started with the hotkey
+    tone: Tone, // Beeper written next to y4m recordings
+    keymap: Keymap,
+    keypad: [bool; 16], // Last keypad state, for the overlay
+    overlay: Overlay,
+    paused: bool,
+    commands: Option<Receiver<String>> // Lines typed on stdin, with --debug
+}
 
 impl Window {
     // Open new window
-    pub fn new(title: &str) -> Result<Window, Error> {
+    pub fn new(title: &str, scale: usize, fullscreen: bool, scaling: Scaling) -> Result<Window, Error> {
+        let win = open(title, scale, fullscreen)?;
 
-        let mut win = minifb::Window::new(
-            title, WIDTH, HEIGHT,
-            WindowOptions {scale: Scale::X16, ..WindowOptions::default() }
-        )?;
+        let mut window = Window::headless();
+        window.win = Some(win);
+        window.title = String::from(title);
+        window.scale = scale;
+        window.fullscreen = fullscreen;
+        window.scaling = scaling;
 
-        win.set_target_fps(60);
+        Ok(window)
+    }
 
-        Ok(Window { win, framebuffer: [PX_OFF; WIDTH * HEIGHT]})
+    // Window that never opens, for running without a display
+    pub fn headless() -> Window {
+        Window {
+            win: None,
+            title: String::new(),
+            scale: 1,
+            fullscreen: false,
+            scaling: Scaling::Integer,
+            buffer: Vec::new(),
+            buffer_width: 0,
+            buffer_height: 0,
+            scaled: Vec::new(),
+            palettes: Palette::themes(),
+            palette_index: 0,
+            filter: Filter::new(FilterMode::None),
+            screenshot_dir: PathBuf::from("."),
+            recorder: None,
+            record_format: String::from("gif"),
+            tone: Tone::default(),
+            keymap: Keymap::default(),
+            keypad: [false; 16],
+            overlay: Overlay::new(),
+            paused: false,
+            commands: None
+        }
+    }
 
+    pub fn set_filter(&mut self, mode: FilterMode) {
+        self.filter = Filter::new(mode);
     }
 
-    // Map keyboard to chip8 keys
-    pub fn handle_key_events(&self) -> [bool; 16] {
-
-        let mut keys = [false; 16];
-
-        // 1 2 3 C
-        // 4 5 6 D
-        // 7 8 9 E
-        // A 0 B F  
-
-        self.win.get_keys().iter().for_each(|k: &Key| {
-            match k {
-                Key::Key1 => keys[0x1] = true,
-                Key::Key2 => keys[0x2] = true,
-                Key::Key3 => keys[0x3] = true,
-                Key::Key4 => keys[0xc] = true,
-                Key::Q => keys[0x4] = true,
-                Key::W => keys[0x5] = true,
-                Key::E => keys[0x6] = true,
-                Key::R => keys[0xd] = true,
-                Key::A => keys[0x7] = true,
-                Key::S => keys[0x8] = true,
-                Key::D => keys[0x9] = true,
-                Key::F => keys[0xe] = true,
-                Key::Z => keys[0xa] = true,
-                Key::X => keys[0x0] = true,
-                Key::C => keys[0xb] = true,
-                Key::V => keys[0xf] = true,
-                _ => ()
+    pub fn set_record_format(&mut self, extension: &str) {
+        self.record_format = String::from(extension);
+    }
+
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.tone = tone;
+    }
+
+    // Record every presented frame from the next refresh on
+    pub fn start_recording(&mut self, path: &std::path::Path) {
+        let (width, height) = if self.buffer.is_empty() { (WIDTH, HEIGHT) } else { (self.buffer_width, self.buffer_height) };
+
+        match Recorder::start(path, width, height, self.tone) {
+            Ok(recorder) => {
+                self.status(&format!("Recording to {}", path.display()));
+                self.recorder = Some(recorder);
+            }
+            Err(err) => self.error(&format!("Could not start recording: {}", err))
+        }
+    }
+
+    pub fn stop_recording(&mut self) {
+        if let Some(mut recorder) = self.recorder.take() {
+            match recorder.finish() {
+                Ok(()) => self.status(&format!("Recording saved to {}", recorder.path().display())),
+                Err(err) => self.error(&format!("Could not finish recording: {}", err))
+            }
+        }
+    }
+
+    pub fn set_screenshot_dir(&mut self, dir: PathBuf) {
+        self.screenshot_dir = dir;
+    }
+
+    // Save the last presented frame with the palette applied, at native
+    // resolution and at the current integer scale
+    pub fn screenshot(&self) -> Result<Vec<PathBuf>, String> {
+        let (width, height) = (self.buffer_width, self.buffer_height);
+        if self.buffer.is_empty() {
+            return Err(String::from("Nothing drawn yet"));
+        }
+
+        let factor = match &self.win {
+            Some(win) => {
+                let (win_width, win_height) = win.get_size();
+                (win_width / width).min(win_height / height).max(1)
+            }
+            None => self.scale
+        };
+
+        let native = screenshot::timestamped_path(&self.screenshot_dir, "", "png");
+        let scaled = native.with_file_name(format!("{}-x{}.png",
+            native.file_stem().unwrap_or_default().to_string_lossy(), factor));
+
+        screenshot::save_png(&native, &self.buffer, width, height)?;
+        screenshot::save_png(&scaled, &screenshot::upscale(&self.buffer, width, height, factor), width * factor, height * factor)?;
+
+        Ok(vec![native, scaled])
+    }
+
+    // Screenshot with the result shown as a status message
+    fn save_screenshot(&mut self) {
+        match self.screenshot() {
+            Ok(paths) => paths.iter().for_each(|p| self.status(&format!("Screenshot saved to {}", p.display()))),
+            Err(err) => self.error(&format!("Could not save screenshot: {}", err))
+        }
+    }
+
+    // Take debugger commands from stdin, one per line, run between frames
+    //
+    //     screenshot    Same as F12
+    pub fn read_commands(&mut self) {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else { break; };
+                if sender.send(line).is_err() {
+                    break;
+                }
             }
         });
+        self.commands = Some(receiver);
+    }
+
+    fn run_commands(&mut self) {
+        let commands: Vec<String> = match &self.commands {
+            Some(receiver) => receiver.try_iter().collect(),
+            None => return
+        };
 
-        keys
+        for command in commands {
+            match command.trim() {
+                "" => (),
+                "screenshot" => self.save_screenshot(),
+                other => eprintln!("Unknown command '{}', expected screenshot", other)
+            }
+        }
     }
 
-    // Utilities
-    pub fn is_key_down(&self, key: Key) -> bool {
-        self.win.is_key_down(key)
+    // minifb cannot change the window style, so open a new one
+    pub fn toggle_fullscreen(&mut self) {
+        if self.win.is_none() {
+            return;
+        }
+
+        match open(&self.title, self.scale, !self.fullscreen) {
+            Ok(win) => {
+                self.win = Some(win);
+                self.fullscreen = !self.fullscreen;
+            }
+            Err(err) => self.error(&format!("Could not toggle fullscreen: {}", err))
+        }
+    }
+
+    fn palette(&self) -> &Palette {
+        &self.palettes[self.palette_index]
     }
 
-    pub fn is_open(&self) -> bool {
-        self.win.is_open()
+    // Use a palette, the built-in themes follow it when cycling
+    pub fn set_palette(&mut self, palette: Palette) {
+        let mut palettes = vec![palette.clone()];
+        palettes.extend(Palette::themes().into_iter().filter(|p| p.name != palette.name));
+
+        self.palettes = palettes;
+        self.palette_index = 0;
     }
 
-    pub fn clear_screen(&mut self) {
-        self.framebuffer = self.framebuffer.map(|_| PX_OFF);
+    pub fn next_palette(&mut self) {
+        self.palette_index = (self.palette_index + 1) % self.palettes.len();
     }
 
-    // Draw window
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
 
-    pub fn refresh(&mut self) {
-        self.win.update_with_buffer(&self.framebuffer, WIDTH, HEIGHT).unwrap();
+    // Print a status message and show it on the overlay
+    pub fn status(&mut self, text: &str) {
+        println!("{}", text);
+        self.overlay.message(text);
     }
 
-    pub fn draw(&mut self, bytes: &Vec<u8>, init_x: u8, init_y: u8) -> u8{
+    fn error(&mut self, text: &str) {
+        eprintln!("{}", text);
+        self.overlay.message(text);
+    }
+}
 
-        // 0 1 0 0 0 1 1 1
-        // 1 1 1 1 0 0 0 0
-        // etc
+impl Frontend for Window {
+    // Closed, or Escape pressed
+    fn is_open(&self) -> bool {
+        self.win.as_ref().is_none_or(|w| w.is_open() && !w.is_key_down(Key::Escape))
+    }
 
-        let mut vf: u8 = 0;
+    // Handle emulator hotkeys, once per press
+    fn handle_hotkeys(&mut self) {
+        let pressed = |key| self.win.as_ref().is_some_and(|w| w.is_key_pressed(key, KeyRepeat::No));
+        let next_theme = pressed(KEY_NEXT_THEME);
+        let overlay = pressed(KEY_OVERLAY);
+        let pause = pressed(KEY_PAUSE);
+        let fullscreen = pressed(KEY_FULLSCREEN);
+        let screenshot = pressed(KEY_SCREENSHOT);
+        let record = pressed(KEY_RECORD);
 
-        // Loop bytes vector  
-        for (vector_index, byte) in bytes.iter().enumerate() {
-            // Loop within each byte
-            for byte_index in 0..8 { //0-7
+        if next_theme {
+            self.next_palette();
+            self.status(&format!("Theme: {}", self.palette().name));
+        }
 
-                let x = (init_x as usize + byte_index) % WIDTH;
-                let y = (init_y as usize + vector_index) % HEIGHT;
+        if overlay {
+            self.overlay.toggle();
+        }
 
-                let coord = (y * WIDTH) + x;
+        if pause {
+            self.paused = !self.paused;
+        }
 
-                // Check if this bit is set in the sprite
-                let sprite_pixel_set = (byte & (1 << (7 - byte_index))) != 0;
-            
-                 // Only process if sprite pixel is set
-                if sprite_pixel_set {
-                    let display_pixel_on = self.framebuffer[coord] == PX_ON;
-                
-                    // XOR: toggle the pixel
-                    self.framebuffer[coord] = if display_pixel_on { PX_OFF } else { PX_ON };
-                    
-                    // Set VF if we're turning off a pixel (collision)
-                    if display_pixel_on {
-                        vf = 1;
-                    }
-                }
+        if fullscreen {
+            self.toggle_fullscreen();
+        }
+
+        if screenshot {
+            self.save_screenshot();
+        }
+
+        if record {
+            if self.recorder.is_some() {
+                self.stop_recording();
+            } else {
+                let path = screenshot::timestamped_path(&self.screenshot_dir, "", &self.record_format);
+                self.start_recording(&path);
             }
         }
 
-        vf
+        self.run_commands();
     }
 
+    // Map keyboard to chip8 keys
+    fn handle_key_events(&mut self) -> [bool; 16] {
+        self.keypad = match &self.win {
+            Some(win) => self.keymap.keypad(&win.get_keys()),
+            None => [false; 16]
+        };
+        self.keypad
+    }
+
+    fn paused(&self) -> bool {
+        self.paused
+    }
+
+    // Render the display with the palette, filter, fit to the window, add the
+    // overlay and present it
+    // Works for any display size, the image is rescaled when it changes
+    // beeping tells the recorder whether the sound timer ran this frame
+    fn refresh(&mut self, display: &Display, beeping: bool, frames: u64) {
+        let palette = &self.palettes[self.palette_index];
+        let background = palette.background();
+        let foreground = palette.color(1);
+
+        self.buffer.clear();
+        self.buffer.extend(display.pixels().iter().map(|&planes| palette.color(planes as usize)));
+        self.buffer_width = display.width();
+        self.buffer_height = display.height();
+
+        // Recordings get the filtered frame, as presented
+        let buffer = self.filter.apply(&self.buffer, background);
+
+        let recorded = match &mut self.recorder {
+            Some(recorder) => recorder.frame(buffer, self.buffer_width, self.buffer_height, beeping),
+            None => Ok(())
+        };
+
+        if let Some(win) = &mut self.win {
+            let (win_width, win_height) = win.get_size();
+            let (win_width, win_height) = (win_width.max(1), win_height.max(1));
+
+            fit(&mut self.scaled, win_width, win_height, buffer, display.width(), display.height(), self.scaling, background);
+            self.overlay.tick(frames);
+            self.overlay.draw(&mut self.scaled, win_width, win_height, &self.keypad, self.paused, foreground, background);
+            win.update_with_buffer(&self.scaled, win_width, win_height).unwrap();
+        }
+
+        if let Err(err) = recorded {
+            eprintln!("Recording stopped: {}", err);
+            self.stop_recording();
+        }
+    }
+}
+
+// Open a resizable window of the display size times scale
+// Fullscreen is the largest scale that fits the screen, without decorations
+fn open(title: &str, scale: usize, fullscreen: bool) -> Result<minifb::Window, Error> {
+    let mut win = if fullscreen {
+        let options = WindowOptions {
+            scale: Scale::FitScreen,
+            scale_mode: ScaleMode::UpperLeft,
+            borderless: true,
+            title: false,
+            topmost: true,
+            ..WindowOptions::default()
+        };
+        let mut win = minifb::Window::new(title, WIDTH, HEIGHT, options)?;
+        win.set_position(0, 0);
+        win
+    } else {
+        let options = WindowOptions {
+            resize: true,
+            scale_mode: ScaleMode::UpperLeft,
+            ..WindowOptions::default()
+        };
+        minifb::Window::new(title, WIDTH * scale, HEIGHT * scale, options)?
+    };
+
+    win.set_target_fps(60);
+
+    Ok(win)
 }
 
+// Nearest neighbour scale of src into a dst of the window size, with
+// background borders
+#[allow(clippy::too_many_arguments)]
+fn fit(dst: &mut Vec<u32>, dst_width: usize, dst_height: usize,
+       src: &[u32], src_width: usize, src_height: usize,
+       scaling: Scaling, background: u32) {
+
+    dst.clear();
+    dst.resize(dst_width * dst_height, background);
+
+    let (width, height) = match scaling {
+        Scaling::Integer => {
+            let factor = (dst_width / src_width).min(dst_height / src_height).max(1);
+            (src_width * factor, src_height * factor)
+        }
+        Scaling::Aspect => {
+            if dst_width * src_height > dst_height * src_width {
+                (src_width * dst_height / src_height, dst_height)
+            } else {
+                (dst_width, src_height * dst_width / src_width)
+            }
+        }
+        Scaling::Stretch => (dst_width, dst_height)
+    };
+
+    let offset_x = dst_width.saturating_sub(width) / 2;
+    let offset_y = dst_height.saturating_sub(height) / 2;
+
+    for y in 0..height.min(dst_height) {
+        let src_row = (y * src_height / height) * src_width;
+        let dst_row = (offset_y + y) * dst_width + offset_x;
+
+        for x in 0..width.min(dst_width) {
+            dst[dst_row + x] = src[src_row + x * src_width / width];
+        }
+    }
+}
@@ -0,0 +1,178 @@
+// Browser frontend for chip8.wasm
+//
+// <canvas class="chip8" data-rom="roms/pong.ch8" data-theme="green"
+//         data-quirks="vip" data-speed="12"></canvas>
+// <script type="module">
+//     import { start } from "./chip8.js";
+//     start(document.querySelector("canvas.chip8"));
+// </script>
+//
+// Keys use the physical QWERTY layout, like the desktop default:
+// 1 2 3 4 / Q W E R / A S D F / Z X C V
+
+const KEYMAP = {
+    Digit1: 0x1, Digit2: 0x2, Digit3: 0x3, Digit4: 0xC,
+    KeyQ: 0x4, KeyW: 0x5, KeyE: 0x6, KeyR: 0xD,
+    KeyA: 0x7, KeyS: 0x8, KeyD: 0x9, KeyF: 0xE,
+    KeyZ: 0xA, KeyX: 0x0, KeyC: 0xB, KeyV: 0xF
+};
+
+const FRAME_MS = 1000 / 60;
+
+// Beeper: square wave faded in and out to avoid clicks
+// Browsers only allow audio after a user gesture, so it starts on the first key or click
+class Beeper {
+    constructor(frequency, volume) {
+        this.frequency = frequency;
+        this.volume = volume;
+        this.context = null;
+    }
+
+    resume() {
+        if (this.context) {
+            return;
+        }
+        this.context = new AudioContext();
+        this.gain = this.context.createGain();
+        this.gain.gain.value = 0;
+        this.gain.connect(this.context.destination);
+
+        const oscillator = this.context.createOscillator();
+        oscillator.type = "square";
+        oscillator.frequency.value = this.frequency;
+        oscillator.connect(this.gain);
+        oscillator.start();
+    }
+
+    close() {
+        if (this.context) {
+            this.context.close();
+            this.context = null;
+        }
+    }
+
+    set(on) {
+        if (this.context) {
+            this.gain.gain.setTargetAtTime(on ? this.volume : 0, this.context.currentTime, 0.005);
+        }
+    }
+}
+
+// Copy a string or bytes into wasm memory
+function pass(exports, bytes) {
+    if (typeof bytes === "string") {
+        bytes = new TextEncoder().encode(bytes);
+    }
+    const ptr = exports.chip8_alloc(bytes.length);
+    new Uint8Array(exports.memory.buffer, ptr, bytes.length).set(bytes);
+    return [ptr, bytes.length];
+}
+
+// Run a ROM on a canvas, options default to the canvas data- attributes
+export async function start(canvas, options = {}) {
+    const settings = { wasm: "chip8.wasm", speed: 12, tone: 440, volume: 0.25, ...canvas.dataset, ...options };
+
+    const { instance } = await WebAssembly.instantiateStreaming(fetch(settings.wasm), {});
+    const wasm = instance.exports;
+
+    const rom = new Uint8Array(settings.romBytes ?? await (await fetch(settings.rom)).arrayBuffer());
+    const machine = wasm.chip8_new(Math.floor(Math.random() * 0xFFFFFFFF));
+
+    const [romPtr, romLen] = pass(wasm, rom);
+    const loaded = wasm.chip8_load(machine, romPtr, romLen);
+    wasm.chip8_dealloc(romPtr, romLen);
+    if (loaded !== 0) {
+        throw new Error("ROM does not fit in memory");
+    }
+
+    for (const [name, setter] of [["quirks", wasm.chip8_set_quirks], ["theme", wasm.chip8_set_theme]]) {
+        if (settings[name]) {
+            const [ptr, len] = pass(wasm, settings[name]);
+            const result = setter(machine, ptr, len);
+            wasm.chip8_dealloc(ptr, len);
+            if (result !== 0) {
+                throw new Error(`Unknown ${name} '${settings[name]}'`);
+            }
+        }
+    }
+    wasm.chip8_set_speed(machine, Number(settings.speed));
+
+    // Keyboard, only while the canvas has focus so the page still scrolls
+    let keys = 0;
+    const beeper = new Beeper(Number(settings.tone), Number(settings.volume));
+    const listeners = new AbortController();
+    const listen = (type, handler) => canvas.addEventListener(type, handler, { signal: listeners.signal });
+    canvas.tabIndex = 0;
+    listen("keydown", (event) => {
+        beeper.resume();
+        if (event.code in KEYMAP) {
+            keys |= 1 << KEYMAP[event.code];
+            event.preventDefault();
+        }
+    });
+    listen("keyup", (event) => {
+        if (event.code in KEYMAP) {
+            keys &= ~(1 << KEYMAP[event.code]);
+        }
+    });
+    listen("blur", () => { keys = 0; });
+    listen("click", () => {
+        beeper.resume();
+        canvas.focus();
+    });
+
+    const context = canvas.getContext("2d");
+    canvas.style.imageRendering = "pixelated";
+
+    // Fixed 60Hz steps, whatever the display refresh rate
+    let last = performance.now();
+    let pending = 0;
+    let running = true;
+
+    function tick(now) {
+        if (!running) {
+            return;
+        }
+        pending = Math.min(pending + now - last, FRAME_MS * 5);
+        last = now;
+
+        let frames = 0;
+        let beeping = false;
+        while (pending >= FRAME_MS) {
+            const result = wasm.chip8_frame(machine, keys);
+            if (result < 0) {
+                running = false;
+                beeper.close();
+                console.error("CPU crashed");
+                return;
+            }
+            beeping ||= result === 1;
+            pending -= FRAME_MS;
+            frames++;
+        }
+
+        if (frames > 0) {
+            beeper.set(beeping);
+
+            const width = wasm.chip8_width(machine);
+            const height = wasm.chip8_height(machine);
+            if (canvas.width !== width || canvas.height !== height) {
+                canvas.width = width;
+                canvas.height = height;
+            }
+            const pixels = new Uint8ClampedArray(wasm.memory.buffer, wasm.chip8_pixels(machine), width * height * 4);
+            context.putImageData(new ImageData(pixels, width, height), 0, 0);
+        }
+
+        requestAnimationFrame(tick);
+    }
+    requestAnimationFrame(tick);
+
+    // Stop the emulator and free the machine
+    return () => {
+        running = false;
+        listeners.abort();
+        beeper.close();
+        wasm.chip8_free(machine);
+    };
+}